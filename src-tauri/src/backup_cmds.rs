@@ -0,0 +1,390 @@
+use log::warn;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::time::{SystemTime, UNIX_EPOCH};
+use surrealdb::dbs::Response;
+use surrealdb::sql::{thing, Object, Value};
+use tauri::AppHandle;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::scan_progress::ScanGuard;
+use crate::settings_cmds::{load_settings, save_settings};
+use crate::types::{BackupManifest, RestoreMode, ReviewFlag, Settings, WorkMetadata};
+use crate::utils::{
+    find_work_by_path, from_value, into_iter_objects, keep_custom_order, object_into_strings,
+    object_into_work_metadata, save_position, save_work_metadata, to_value, value_into_json,
+};
+use crate::DB;
+use crate::SES;
+
+const BACKUP_FORMAT: &str = "audiobookplayer-backup";
+/// Bumped whenever the archive layout changes, older versions must stay
+/// restorable.
+const BACKUP_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const SETTINGS_FILE: &str = "settings.json";
+const DATABASE_FILE: &str = "database.json";
+
+/// Tables holding user state, in the order they are restored. Caches such as
+/// `track_metadata` are left out and rebuilt by the next scan.
+const BACKUP_TABLES: [&str; 8] = [
+    "authors",
+    "works",
+    "wrote",
+    "narrated",
+    "times",
+    "merged_paths",
+    "duplicate_ignores",
+    "scan_reports",
+];
+/// Graph edges between authors and works, restored with `RELATE`.
+const EDGE_TABLES: [&str; 2] = ["wrote", "narrated"];
+
+/// Record links come out of the database as "table:id" strings.
+static RECORD_LINK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:works|authors):[A-Za-z0-9_]+$").unwrap());
+static RECORD_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\w+):[A-Za-z0-9_]+$").unwrap());
+
+/// Writes the library database and settings to a zip archive at `path`.
+#[tauri::command]
+pub async fn backup_library(app_handle: AppHandle, path: String) -> Result<(), String> {
+    let settings = load_settings(app_handle.clone()).await?;
+    let mut database = Map::new();
+    for table in BACKUP_TABLES {
+        database.insert(table.to_owned(), JsonValue::Array(load_table(table).await?));
+    }
+    let manifest = BackupManifest {
+        format: BACKUP_FORMAT.to_owned(),
+        version: BACKUP_VERSION,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        app_version: app_handle.package_info().version.to_string(),
+    };
+
+    let file = File::create(&path).map_err(|err| format!("failed to create {}: {}", path, err))?;
+    let mut archive = ZipWriter::new(file);
+    write_entry(&mut archive, MANIFEST_FILE, &manifest)?;
+    write_entry(&mut archive, SETTINGS_FILE, &settings)?;
+    write_entry(&mut archive, DATABASE_FILE, &database)?;
+    archive
+        .finish()
+        .map(|_| ())
+        .map_err(|err| format!("failed to write backup: {}", err))
+}
+
+/// Restores a backup made by `backup_library`, returns how many records of
+/// each table were restored. `Merge` keeps the current settings unless no
+/// library folder is configured yet.
+#[tauri::command]
+pub async fn restore_library(
+    app_handle: AppHandle,
+    path: String,
+    mode: RestoreMode,
+) -> Result<BTreeMap<String, usize>, String> {
+    let _guard =
+        ScanGuard::acquire().map_err(|_| "cannot restore while a scan is running".to_owned())?;
+
+    let file = File::open(&path).map_err(|err| format!("failed to open {}: {}", path, err))?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|err| format!("{} is not a backup: {}", path, err))?;
+    let manifest: BackupManifest = read_entry(&mut archive, MANIFEST_FILE)?;
+    check_manifest(&manifest)?;
+    let settings: Settings = read_entry(&mut archive, SETTINGS_FILE)?;
+    let mut database: BTreeMap<String, Vec<JsonValue>> = read_entry(&mut archive, DATABASE_FILE)?;
+
+    let current_settings = load_settings(app_handle.clone()).await?;
+    if mode == RestoreMode::Replace || current_settings.roots().is_empty() {
+        save_settings(app_handle, settings).await?;
+    }
+    if mode == RestoreMode::Replace {
+        let ass = BACKUP_TABLES
+            .iter()
+            .map(|x| format!("REMOVE TABLE {};", x))
+            .collect::<String>();
+        execute(ass, None).await?;
+    }
+
+    // ids of backed up works that exist here under another id
+    let mut ids = HashMap::new();
+    // works merged into, their people stay as scanned
+    let mut kept = HashSet::new();
+    let mut restored = BTreeMap::new();
+    for table in BACKUP_TABLES {
+        let mut count = 0;
+        for record in database.remove(table).unwrap_or_default() {
+            match restore_record(mode, table, record, &mut ids, &mut kept).await {
+                Ok(()) => count += 1,
+                Err(err) => warn!("Failed to restore {} record: {}", table, err),
+            }
+        }
+        restored.insert(table.to_owned(), count);
+    }
+    Ok(restored)
+}
+
+fn check_manifest(manifest: &BackupManifest) -> Result<(), String> {
+    if manifest.format != BACKUP_FORMAT {
+        return Err("the file is not a library backup".into());
+    }
+    if manifest.version == 0 || manifest.version > BACKUP_VERSION {
+        return Err(format!(
+            "backup version {} from app version {} is not supported, up to version {} can be restored",
+            manifest.version, manifest.app_version, BACKUP_VERSION
+        ));
+    }
+    Ok(())
+}
+
+async fn load_table(table: &str) -> Result<Vec<JsonValue>, String> {
+    let result = execute(format!("SELECT * FROM {}", table), None).await?;
+    Ok(into_iter_objects(result)?
+        .filter_map(Result::ok)
+        .map(|object| value_into_json(Value::Object(object)))
+        .collect())
+}
+
+async fn restore_record(
+    mode: RestoreMode,
+    table: &str,
+    record: JsonValue,
+    ids: &mut HashMap<String, String>,
+    kept: &mut HashSet<String>,
+) -> Result<(), String> {
+    let JsonValue::Object(mut record) = record else {
+        return Err("record is not an object".into());
+    };
+    let id = record
+        .remove("id")
+        .and_then(|x| x.as_str().map(str::to_owned))
+        .filter(|x| RECORD_ID.captures(x).map_or(false, |x| &x[1] == table));
+
+    if EDGE_TABLES.contains(&table) {
+        let (Some(from), Some(to)) = (link(record.get("in"), ids), link(record.get("out"), ids))
+        else {
+            return Err("edge without both ends".into());
+        };
+        if kept.contains(&to) {
+            return Ok(());
+        }
+        return execute(
+            format!(
+                "DELETE {table} WHERE in = {from} AND out = {to}; RELATE {from}->{table}->{to};"
+            ),
+            None,
+        )
+        .await
+        .map(|_| ());
+    }
+    if table == "times" {
+        return restore_time(&record, ids).await;
+    }
+
+    let mut target = id.clone();
+    if table == "works" {
+        // the same folder already in the library keeps its id, as does the
+        // same work moved to another folder since the backup
+        let path = record
+            .get("path")
+            .and_then(|x| x.as_str())
+            .unwrap_or_default();
+        let mut existing = find_work_by_path(path).await?;
+        if existing.is_none() {
+            if let Some(id) = &id {
+                existing = find_work_by_id(id).await?;
+            }
+        }
+        if let Some(existing) = existing {
+            let existing_id = existing
+                .get("id")
+                .map(|x| x.clone().as_string())
+                .unwrap_or_default();
+            if let Some(id) = &id {
+                ids.insert(id.clone(), existing_id.clone());
+            }
+            if mode == RestoreMode::Merge {
+                kept.insert(existing_id.clone());
+                return restore_user_state(&existing_id, &existing, &record).await;
+            }
+            target = Some(existing_id);
+        }
+        if let Some(target) = &target {
+            execute(
+                format!("DELETE wrote WHERE out = {target}; DELETE narrated WHERE out = {target};"),
+                None,
+            )
+            .await?;
+        }
+    }
+
+    let content = restore_links(to_value(&record)?, ids);
+    let ass = match target {
+        Some(target) => format!("UPDATE {} CONTENT $record", target),
+        None => format!("CREATE {} CONTENT $record", table),
+    };
+    execute(ass, Some(BTreeMap::from([("record".into(), content)])))
+        .await
+        .map(|_| ())
+}
+
+async fn find_work_by_id(id: &str) -> Result<Option<Object>, String> {
+    let Ok(work) = thing(id) else {
+        return Ok(None);
+    };
+    let result = execute(
+        "SELECT * FROM $work".into(),
+        Some(BTreeMap::from([("work".into(), Value::Thing(work))])),
+    )
+    .await?;
+    into_iter_objects(result)?.next().transpose()
+}
+
+/// Takes what the user set on a backed up work onto the scanned work it
+/// matched: locked metadata fields, a manual file order and the review
+/// flags still open in the backup. Everything else stays as scanned.
+async fn restore_user_state(
+    work_id: &str,
+    existing: &Object,
+    record: &Map<String, JsonValue>,
+) -> Result<(), String> {
+    let backup: WorkMetadata = record
+        .get("metadata")
+        .and_then(|x| serde_json::from_value(x.clone()).ok())
+        .unwrap_or_default();
+    let mut metadata = object_into_work_metadata(existing);
+    if !backup.locked.is_empty() {
+        metadata.take_locked(&backup);
+        save_work_metadata(work_id, &metadata).await?;
+    }
+
+    // a flag missing from the backup was dismissed there
+    let mut review: Vec<ReviewFlag> = existing
+        .get("review")
+        .and_then(|x| from_value(x.clone()).ok())
+        .unwrap_or_default();
+    if let Some(backup_review) = record
+        .get("review")
+        .and_then(|x| serde_json::from_value::<Vec<ReviewFlag>>(x.clone()).ok())
+    {
+        review.retain(|x| backup_review.contains(x));
+    }
+    review.retain(|x| !metadata.is_locked(x.field));
+
+    let mut ass = format!("UPDATE {} SET review = $review", work_id);
+    let mut data: BTreeMap<String, Value> = BTreeMap::from([("review".into(), to_value(&review)?)]);
+    if record.get("custom_order").and_then(|x| x.as_bool()) == Some(true) {
+        let order = record
+            .get("audio_files")
+            .and_then(|x| serde_json::from_value(x.clone()).ok())
+            .unwrap_or_default();
+        let audio_files = keep_custom_order(order, object_into_strings(existing, "audio_files"));
+        ass.push_str(", audio_files = $audio_files, custom_order = true");
+        data.insert("audio_files".into(), audio_files.into());
+    }
+    execute(ass, Some(data)).await.map(|_| ())
+}
+
+/// Keeps the furthest position when the work already has a resume time.
+async fn restore_time(
+    record: &Map<String, JsonValue>,
+    ids: &HashMap<String, String>,
+) -> Result<(), String> {
+    let Some(work) = link(record.get("work"), ids) else {
+        return Err("time without work".into());
+    };
+    let position = record
+        .get("position")
+        .and_then(|x| x.as_f64())
+        .unwrap_or_default();
+
+    let result = execute(
+        format!("SELECT position FROM times WHERE work = {}", work),
+        None,
+    )
+    .await?;
+    let current = into_iter_objects(result)?
+        .filter_map(Result::ok)
+        .filter_map(|x| x.get("position").map(|x| x.clone().as_float()))
+        .reduce(f64::max);
+    if current.map_or(false, |x| x >= position) {
+        return Ok(());
+    }
+    save_position(&work, position).await
+}
+
+fn link(value: Option<&JsonValue>, ids: &HashMap<String, String>) -> Option<String> {
+    let value = value?.as_str()?;
+    if !RECORD_LINK.is_match(value) {
+        return None;
+    }
+    Some(ids.get(value).cloned().unwrap_or_else(|| value.to_owned()))
+}
+
+/// Turns the "table:id" strings of a backed up record into record links
+/// again, pointing them at the works they were matched to.
+fn restore_links(value: Value, ids: &HashMap<String, String>) -> Value {
+    match value {
+        Value::Strand(text) if RECORD_LINK.is_match(text.as_str()) => {
+            let id = ids.get(text.as_str()).map_or(text.as_str(), String::as_str);
+            thing(id).map(Value::Thing).unwrap_or(Value::Strand(text))
+        }
+        Value::Array(values) => Value::Array(
+            values
+                .0
+                .into_iter()
+                .map(|x| restore_links(x, ids))
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .0
+                .into_iter()
+                .map(|(key, x)| (key, restore_links(x, ids)))
+                .collect::<BTreeMap<String, Value>>()
+                .into(),
+        ),
+        value => value,
+    }
+}
+
+fn write_entry<T: Serialize>(
+    archive: &mut ZipWriter<File>,
+    name: &str,
+    item: &T,
+) -> Result<(), String> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    archive
+        .start_file(name, options)
+        .map_err(|err| format!("failed to write backup: {}", err))?;
+    serde_json::to_writer(archive, item).map_err(|err| format!("failed to write {}: {}", name, err))
+}
+
+fn read_entry<T: DeserializeOwned>(
+    archive: &mut ZipArchive<File>,
+    name: &str,
+) -> Result<T, String> {
+    let entry = archive
+        .by_name(name)
+        .map_err(|err| format!("backup has no {}: {}", name, err))?;
+    serde_json::from_reader(entry).map_err(|err| format!("invalid {} in backup: {}", name, err))
+}
+
+async fn execute(
+    ass: String,
+    data: Option<BTreeMap<String, Value>>,
+) -> Result<Vec<Response>, String> {
+    DB.get()
+        .expect("DB does not exist")
+        .execute(ass.as_str(), &SES, data, false)
+        .await
+        .map_err(|err| format!("query failed: {}", err))
+}
@@ -1,27 +1,40 @@
 use log::error;
+use std::collections::BTreeMap;
+use surrealdb::sql::Value;
 use tauri::Manager;
 
+use crate::track_cache::load_track_metadata;
 use crate::types::{
-    AddWorkTimeError, LoadWorksError, ReadFileMetadataError, ReadWorkDataError, TrackMetadata, Work,
+    AddWorkTimeError, LoadWorksError, ReadWorkDataError, Work, WorkMetadata, WorkTracks,
+};
+use crate::utils::{
+    into_iter_objects, object_into_work, order_audio_files, save_position, save_work_metadata,
+    WORK_FETCH, WORK_FIELDS,
 };
-use crate::utils::{into_iter_objects, object_into_work, read_file_metadata};
 use crate::DB;
 use crate::SES;
 
 #[tauri::command]
-pub async fn start_book(app_handle: tauri::AppHandle, work_id: String) {
-    let work = load_work(work_id).await.unwrap();
+pub async fn start_book(
+    app_handle: tauri::AppHandle,
+    work_id: String,
+) -> Result<(), LoadWorksError> {
+    let work = load_work(work_id).await?;
 
     app_handle.emit_all("work_loaded", work.clone()).unwrap();
 
-    let files: Vec<TrackMetadata> = work
-        .audio_files
-        .iter()
-        .map(|path: &String| read_file_metadata(path.clone()).unwrap())
-        .collect::<Vec<TrackMetadata>>();
+    let files = load_track_metadata(&work.audio_files).await;
+    if !files.errors.is_empty() {
+        app_handle
+            .emit_all("metadata_errors", files.errors.clone())
+            .unwrap();
+    }
 
-    app_handle.emit_all("metadata_loaded", files).unwrap();
+    app_handle
+        .emit_all("metadata_loaded", files.tracks)
+        .unwrap();
     app_handle.emit_all("play", ()).unwrap();
+    Ok(())
 }
 
 #[tauri::command]
@@ -37,21 +50,16 @@ pub async fn load_book_time(work_id: String) -> Result<Option<f64>, ReadWorkData
         return Err(ReadWorkDataError {});
     };
 
-    let Some(Ok(obj)) = objects.last() else {
-        return Ok(None);
-    };
-
-    let dur = obj
-        .get("position")
-        .map(|x| x.clone().as_float())
-        .unwrap_or_default();
-
-    Ok(Some(dur))
+    // older versions added a row on every save, the furthest one wins
+    Ok(objects
+        .filter_map(Result::ok)
+        .filter_map(|x| x.get("position").map(|x| x.clone().as_float()))
+        .reduce(f64::max))
 }
 
 #[tauri::command]
 pub async fn load_work(work_id: String) -> Result<Work, LoadWorksError> {
-    let ass = format!("SELECT * FROM {work_id} FETCH author");
+    let ass = format!("SELECT {WORK_FIELDS} FROM {work_id} {WORK_FETCH}");
     let Ok(result) = DB.get().expect("DB does not exist").execute(ass.as_str(), &SES, None, false).await else {
         return Err(LoadWorksError);
     };
@@ -69,28 +77,102 @@ pub async fn load_work(work_id: String) -> Result<Work, LoadWorksError> {
     Ok(work)
 }
 
+/// Loads the track metadata of a work's audio files, see [`WorkTracks`].
 #[tauri::command]
-pub async fn load_work_metadata(
+pub async fn load_work_metadata(work_id: String) -> Result<WorkTracks, LoadWorksError> {
+    let work = load_work(work_id).await?;
+
+    Ok(load_track_metadata(&work.audio_files).await)
+}
+
+/// Saves user edits to a work's metadata. Every field that changed is locked
+/// so later scans keep the correction.
+#[tauri::command]
+pub async fn update_work_metadata(
     work_id: String,
-) -> Result<Vec<TrackMetadata>, ReadFileMetadataError> {
-    let work = load_work(work_id).await.unwrap();
+    mut metadata: WorkMetadata,
+) -> Result<Work, String> {
+    let Ok(work) = load_work(work_id.clone()).await else {
+        return Err("failed to load work".into());
+    };
 
-    let files: Vec<TrackMetadata> = work
-        .audio_files
-        .iter()
-        .map(|path: &String| read_file_metadata(path.clone()).unwrap())
-        .collect::<Vec<TrackMetadata>>();
+    for field in work.metadata.changed_fields(&metadata) {
+        if !metadata.is_locked(field) {
+            metadata.locked.push(field);
+        }
+    }
+
+    save_work_metadata(&work_id, &metadata).await?;
 
-    Ok(files)
+    load_work(work_id)
+        .await
+        .map_err(|_| "failed to load work".into())
 }
 
+/// Stores a manual playback order for a work's audio files, kept across rescans.
 #[tauri::command]
-pub async fn update_work_time(work_id: String, position: f64) -> Result<(), AddWorkTimeError> {
-    // todo: fix times not updating on duplicate keys
+pub async fn reorder_work_files(work_id: String, audio_files: Vec<String>) -> Result<Work, String> {
+    let Ok(work) = load_work(work_id.clone()).await else {
+        return Err("failed to load work".into());
+    };
+
+    let mut current = work.audio_files.clone();
+    let mut requested = audio_files.clone();
+    current.sort();
+    requested.sort();
+    if current != requested {
+        return Err("reordered files do not match the work's audio files".into());
+    }
+
+    save_audio_file_order(&work_id, audio_files, true).await?;
+
+    load_work(work_id)
+        .await
+        .map_err(|_| "failed to load work".into())
+}
+
+/// Drops a manual file order and goes back to track number and file name order.
+#[tauri::command]
+pub async fn reset_work_file_order(work_id: String) -> Result<Work, String> {
+    let Ok(work) = load_work(work_id.clone()).await else {
+        return Err("failed to load work".into());
+    };
+
+    save_audio_file_order(&work_id, order_audio_files(work.audio_files), false).await?;
+
+    load_work(work_id)
+        .await
+        .map_err(|_| "failed to load work".into())
+}
+
+async fn save_audio_file_order(
+    work_id: &str,
+    audio_files: Vec<String>,
+    custom_order: bool,
+) -> Result<(), String> {
     let ass = format!(
-        "INSERT INTO times (work, position) VALUES ({},{}) ON DUPLICATE KEY UPDATE position={};",
-        work_id, position, position
+        "UPDATE {} SET audio_files = $audio_files, custom_order = $custom_order",
+        work_id
     );
+    let data: BTreeMap<String, Value> = BTreeMap::from([
+        ("audio_files".into(), audio_files.into()),
+        ("custom_order".into(), custom_order.into()),
+    ]);
+    match DB
+        .get()
+        .expect("DB does not exist")
+        .execute(ass.as_str(), &SES, Some(data), false)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("failed to save file order: {}", err)),
+    }
+}
+
+/// Marks the folder and tag disagreements of a work as reviewed.
+#[tauri::command]
+pub async fn dismiss_work_review(work_id: String) -> Result<(), String> {
+    let ass = format!("UPDATE {} SET review = []", work_id);
     match DB
         .get()
         .expect("DB does not exist")
@@ -100,11 +182,19 @@ pub async fn update_work_time(work_id: String, position: f64) -> Result<(), AddW
         Ok(_) => Ok(()),
         Err(err) => {
             error!("{:?}", err);
-            Err(AddWorkTimeError)
+            Err("failed to dismiss review".into())
         }
     }
 }
 
+#[tauri::command]
+pub async fn update_work_time(work_id: String, position: f64) -> Result<(), AddWorkTimeError> {
+    save_position(&work_id, position).await.map_err(|err| {
+        error!("{}", err);
+        AddWorkTimeError
+    })
+}
+
 #[tauri::command]
 pub async fn clear_book_time(work_id: String) -> Result<(), String> {
     let ass = format!("DELETE times WHERE work={}", work_id);
@@ -0,0 +1,239 @@
+use image::ImageFormat;
+use lofty::TaggedFileExt;
+use log::{error, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::types::CoverArt;
+use crate::utils::{
+    get_files_by_extension, order_audio_files, stored_path, AUDIO_FILE_EXTENSIONS,
+    IMAGE_FILE_EXTENSIONS,
+};
+
+/// Folder below the app cache dir holding covers named by content hash.
+pub const COVER_CACHE_DIR: &str = "covers";
+
+/// Longest side in pixels of the generated thumbnails.
+const SMALL_THUMBNAIL: u32 = 160;
+const MEDIUM_THUMBNAIL: u32 = 480;
+
+/// Folder image names that are a cover for sure, best first.
+const COVER_NAMES: [&str; 3] = ["cover", "folder", "front"];
+
+pub fn cover_cache_dir(app_handle: &tauri::AppHandle) -> Option<PathBuf> {
+    app_handle
+        .path_resolver()
+        .app_cache_dir()
+        .map(|x| x.join(COVER_CACHE_DIR))
+}
+
+struct CoverImage {
+    data: Vec<u8>,
+    /// Sniffed from the data, see `sniff_image_type`.
+    extension: &'static str,
+}
+
+/// Picks the best cover among the folder images and the pictures embedded in
+/// the first audio file of a work and stores it in the cache, `None` when the
+/// work has no picture at all.
+pub fn resolve_work_cover(cache_dir: &Path, files: &[String]) -> Option<CoverArt> {
+    let image_files = get_files_by_extension(files.to_vec(), IMAGE_FILE_EXTENSIONS.to_vec());
+    let pictures = order_audio_files(get_files_by_extension(
+        files.to_vec(),
+        AUDIO_FILE_EXTENSIONS.to_vec(),
+    ))
+    .first()
+    .map(|path| read_embedded_pictures(path))
+    .unwrap_or_default();
+
+    let cover = best_cover(&image_files, &pictures)?;
+    match cache_cover(cache_dir, &cover) {
+        Ok(cover) => Some(cover),
+        Err(err) => {
+            error!("Failed to cache cover: {}", err);
+            None
+        }
+    }
+}
+
+/// Caches a downloaded cover image, `source` names it in log messages.
+pub fn cache_downloaded_cover(cache_dir: &Path, data: Vec<u8>, source: &str) -> Option<CoverArt> {
+    let cover = image_from_bytes(data, source)?;
+    match cache_cover(cache_dir, &cover) {
+        Ok(cover) => Some(cover),
+        Err(err) => {
+            error!("Failed to cache cover: {}", err);
+            None
+        }
+    }
+}
+
+/// A folder image named like a cover beats an embedded front cover, which
+/// beats the largest folder image, which beats any other embedded picture.
+fn best_cover(image_files: &[String], pictures: &[lofty::Picture]) -> Option<CoverImage> {
+    let named = image_files
+        .iter()
+        .filter_map(|file| {
+            let stem = stored_path(file).file_stem()?.to_str()?.to_lowercase();
+            let rank = COVER_NAMES.iter().position(|x| *x == stem)?;
+            Some((rank, file))
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, file)| file);
+    let largest = image_files.iter().max_by_key(|file| {
+        fs::metadata(stored_path(file))
+            .map(|x| x.len())
+            .unwrap_or_default()
+    });
+    let front = pictures
+        .iter()
+        .find(|x| x.pic_type() == lofty::PictureType::CoverFront);
+
+    named
+        .and_then(read_image_file)
+        .or_else(|| front.and_then(embedded_image))
+        .or_else(|| largest.and_then(read_image_file))
+        .or_else(|| pictures.iter().find_map(embedded_image))
+}
+
+fn read_embedded_pictures(path: &str) -> Vec<lofty::Picture> {
+    let tagged = lofty::Probe::open(stored_path(path)).and_then(|x| {
+        x.options(lofty::ParseOptions::new().read_properties(false))
+            .read()
+    });
+    match tagged {
+        Ok(tagged) => tagged
+            .primary_tag()
+            .or_else(|| tagged.first_tag())
+            .map(|tag| tag.pictures().to_vec())
+            .unwrap_or_default(),
+        Err(err) => {
+            warn!("Failed to read pictures of {:?}: {}", path, err);
+            vec![]
+        }
+    }
+}
+
+fn read_image_file(path: &String) -> Option<CoverImage> {
+    match fs::read(stored_path(path)) {
+        Ok(data) => image_from_bytes(data, path),
+        Err(err) => {
+            warn!("Failed to read cover {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+fn embedded_image(picture: &lofty::Picture) -> Option<CoverImage> {
+    image_from_bytes(picture.data().to_vec(), "embedded picture")
+}
+
+/// Extensions and mime types are often wrong, the type of a cover is taken
+/// from its first bytes instead.
+fn image_from_bytes(data: Vec<u8>, source: &str) -> Option<CoverImage> {
+    match sniff_image_type(&data) {
+        Some(extension) => Some(CoverImage { data, extension }),
+        None => {
+            warn!("Unknown image type of {:?}", source);
+            None
+        }
+    }
+}
+
+/// Image types `image` is built to decode. AVIF would need the native dav1d
+/// library, so AVIF covers are served at full size without thumbnails.
+const THUMBNAIL_DECODABLE: [&str; 5] = ["jpg", "png", "gif", "bmp", "webp"];
+
+/// Recognises jpeg, png, gif, bmp, webp and avif images by their magic bytes.
+fn sniff_image_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xFF, 0xD8, 0xFF, ..] => Some("jpg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("gif"),
+        [b'B', b'M', ..] => Some("bmp"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => Some("avif"),
+        _ => None,
+    }
+}
+
+/// Stores a cover as `<hash>.<ext>` with `<hash>-small.jpg` and
+/// `<hash>-medium.jpg` thumbnails, files already cached are reused.
+fn cache_cover(cache_dir: &Path, cover: &CoverImage) -> Result<CoverArt, String> {
+    fs::create_dir_all(cache_dir).map_err(|err| err.to_string())?;
+
+    let hash = Sha256::digest(&cover.data)
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    let path = cache_dir.join(format!("{}.{}", hash, cover.extension));
+    if !path.exists() {
+        fs::write(&path, &cover.data).map_err(|err| err.to_string())?;
+    }
+
+    let small = cache_dir.join(format!("{}-small.jpg", hash));
+    let medium = cache_dir.join(format!("{}-medium.jpg", hash));
+    let thumbnails = if small.exists() && medium.exists() {
+        true
+    } else if !THUMBNAIL_DECODABLE.contains(&cover.extension) {
+        false
+    } else {
+        write_thumbnails(
+            &cover.data,
+            &[(&small, SMALL_THUMBNAIL), (&medium, MEDIUM_THUMBNAIL)],
+        )
+    };
+
+    let to_string = |x: &PathBuf| x.to_string_lossy().to_string();
+    Ok(CoverArt {
+        hash,
+        path: to_string(&path),
+        small: thumbnails.then(|| to_string(&small)),
+        medium: thumbnails.then(|| to_string(&medium)),
+    })
+}
+
+fn write_thumbnails(data: &[u8], sizes: &[(&PathBuf, u32)]) -> bool {
+    let image = match image::load_from_memory(data) {
+        Ok(image) => image,
+        Err(err) => {
+            warn!("Failed to decode cover for thumbnails: {}", err);
+            return false;
+        }
+    };
+    sizes.iter().all(|(path, size)| {
+        let written = image
+            .thumbnail(*size, *size)
+            .to_rgb8()
+            .save_with_format(path, ImageFormat::Jpeg);
+        if let Err(err) = &written {
+            warn!("Failed to write thumbnail {:?}: {}", path, err);
+        }
+        written.is_ok()
+    })
+}
+
+/// Deletes cached covers and thumbnails whose hash is not in `used`, returns
+/// how many files were removed.
+pub fn collect_cover_garbage(cache_dir: &Path, used: &HashSet<String>) -> usize {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let hash = name.split(['.', '-']).next().unwrap_or_default();
+            !used.contains(hash)
+        })
+        .filter(|entry| match fs::remove_file(entry.path()) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Failed to remove {:?}: {}", entry.path(), err);
+                false
+            }
+        })
+        .count()
+}
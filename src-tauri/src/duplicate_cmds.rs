@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use surrealdb::sql::{thing, Value};
+
+use crate::library_cmds::{load_library, load_positions};
+use crate::naming::{normalise_name, normalise_person};
+use crate::types::{DuplicateGroup, DuplicateReason, Work};
+use crate::utils::{delete_work, into_iter_objects, object_into_strings, save_position, to_value};
+use crate::DB;
+use crate::SES;
+
+/// Lowest score for two works to be offered as duplicates.
+const DUPLICATE_THRESHOLD: f64 = 0.6;
+
+/// Share of the longer duration by which two copies of a book may differ.
+const DURATION_TOLERANCE: f64 = 0.02;
+
+/// Duration difference in seconds that is always tolerated.
+const MIN_DURATION_TOLERANCE: f64 = 60.0;
+
+#[tauri::command]
+pub async fn find_duplicates() -> Result<Vec<DuplicateGroup>, String> {
+    let works = load_library()
+        .await
+        .map_err(|_| "failed to load library".to_owned())?;
+    let ignored = load_ignored_pairs().await?;
+    Ok(duplicate_groups(&works, &ignored))
+}
+
+/// Groups works scoring at least `DUPLICATE_THRESHOLD` with one another,
+/// leaving out the pairs the user ignored.
+fn duplicate_groups(works: &[Work], ignored: &HashSet<(String, String)>) -> Vec<DuplicateGroup> {
+    let mut matches = vec![];
+    for (a, b) in candidate_pairs(works) {
+        if ignored.contains(&pair_key(&works[a].id, &works[b].id)) {
+            continue;
+        }
+        let (score, reasons) = compare_works(&works[a], &works[b]);
+        if score >= DUPLICATE_THRESHOLD {
+            matches.push((a, b, score, reasons));
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..works.len()).collect();
+    for (a, b, _, _) in matches.iter() {
+        let (a, b) = (find_root(&mut parents, *a), find_root(&mut parents, *b));
+        parents[b] = a;
+    }
+
+    let mut grouped: BTreeMap<usize, (BTreeSet<usize>, f64, Vec<DuplicateReason>)> =
+        BTreeMap::new();
+    for (a, b, score, reasons) in matches {
+        let root = find_root(&mut parents, a);
+        let (members, confidence, group_reasons) =
+            grouped
+                .entry(root)
+                .or_insert((BTreeSet::new(), 1.0, vec![]));
+        members.extend([a, b]);
+        *confidence = confidence.min(score);
+        for reason in reasons {
+            if !group_reasons.contains(&reason) {
+                group_reasons.push(reason);
+            }
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = grouped
+        .into_values()
+        .map(|(members, confidence, reasons)| DuplicateGroup {
+            works: members.into_iter().map(|x| works[x].clone()).collect(),
+            confidence,
+            reasons,
+        })
+        .collect();
+    groups.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    groups
+}
+
+/// Keeps `keep_id` and deletes the other works of a duplicate group, carrying
+/// over the furthest listening position of the group. The folders of the
+/// deleted works are remembered so later scans do not add them back, until
+/// the kept work itself is deleted.
+#[tauri::command]
+pub async fn merge_duplicates(keep_id: String, remove_ids: Vec<String>) -> Result<(), String> {
+    if remove_ids.contains(&keep_id) {
+        return Err("the kept work cannot be removed".into());
+    }
+    let works = load_library()
+        .await
+        .map_err(|_| "failed to load library".to_owned())?;
+    let ignored = load_ignored_pairs().await?;
+    let groups = duplicate_groups(&works, &ignored);
+    let Some(group) = groups
+        .iter()
+        .find(|group| group.works.iter().any(|x| x.id == keep_id))
+    else {
+        return Err("the kept work has no duplicates".into());
+    };
+    if remove_ids
+        .iter()
+        .any(|id| !group.works.iter().any(|x| &x.id == id))
+    {
+        return Err("only duplicates of the kept work can be removed".into());
+    }
+    let keep = thing(&keep_id).map_err(|_| "invalid work id".to_owned())?;
+    let positions = load_positions()
+        .await
+        .map_err(|_| "failed to load times".to_owned())?;
+
+    let furthest = remove_ids
+        .iter()
+        .chain([&keep_id])
+        .filter_map(|id| positions.get(id))
+        .copied()
+        .reduce(f64::max);
+    if let Some(position) = furthest {
+        save_position(&keep_id, position).await?;
+    }
+
+    // folders merged into a removed work now belong to the kept one
+    for work in group.works.iter().filter(|x| remove_ids.contains(&x.id)) {
+        let removed = thing(&work.id).map_err(|_| "invalid work id".to_owned())?;
+        let data: BTreeMap<String, Value> = BTreeMap::from([
+            ("path".into(), work.path.as_str().into()),
+            ("work".into(), Value::Thing(keep.clone())),
+            ("removed".into(), Value::Thing(removed)),
+        ]);
+        DB.get()
+            .expect("DB does not exist")
+            .execute(
+                "UPDATE merged_paths SET work = $work WHERE work = $removed; \
+                CREATE merged_paths SET path = $path, work = $work",
+                &SES,
+                Some(data),
+                false,
+            )
+            .await
+            .map_err(|err| format!("failed to merge work: {}", err))?;
+        delete_work(&work.id).await?;
+    }
+    Ok(())
+}
+
+/// Stops offering these works as duplicates of each other.
+#[tauri::command]
+pub async fn ignore_duplicates(work_ids: Vec<String>) -> Result<(), String> {
+    let data: BTreeMap<String, Value> = BTreeMap::from([("works".into(), to_value(&work_ids)?)]);
+    DB.get()
+        .expect("DB does not exist")
+        .execute(
+            "CREATE duplicate_ignores SET works = $works",
+            &SES,
+            Some(data),
+            false,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("failed to ignore duplicates: {}", err))
+}
+
+/// Folders of works merged into another, skipped by the scanners.
+pub async fn load_merged_paths() -> Result<HashSet<String>, String> {
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute("SELECT path FROM merged_paths", &SES, None, false)
+        .await
+        .map_err(|err| format!("failed to load merged paths: {}", err))?;
+
+    Ok(into_iter_objects(result)?
+        .filter_map(Result::ok)
+        .filter_map(|object| Some(object.get("path")?.clone().as_string()))
+        .collect())
+}
+
+async fn load_ignored_pairs() -> Result<HashSet<(String, String)>, String> {
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute("SELECT works FROM duplicate_ignores", &SES, None, false)
+        .await
+        .map_err(|err| format!("failed to load ignored duplicates: {}", err))?;
+
+    let mut pairs = HashSet::new();
+    for object in into_iter_objects(result)?.filter_map(Result::ok) {
+        let ids = object_into_strings(&object, "works");
+        for (i, a) in ids.iter().enumerate() {
+            for b in ids.iter().skip(i + 1) {
+                pairs.insert(pair_key(a, b));
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+fn pair_key(a: &str, b: &str) -> (String, String) {
+    if a < b {
+        (a.to_owned(), b.to_owned())
+    } else {
+        (b.to_owned(), a.to_owned())
+    }
+}
+
+/// Pairs of works sharing an audio file or a title, without either of them
+/// two works cannot reach `DUPLICATE_THRESHOLD`.
+fn candidate_pairs(works: &[Work]) -> BTreeSet<(usize, usize)> {
+    let mut buckets: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, work) in works.iter().enumerate() {
+        let title = normalise_name(&work.name);
+        let keys = work
+            .fingerprints
+            .iter()
+            .map(|x| format!("fingerprint:{}", x))
+            .chain((!title.is_empty()).then(|| format!("title:{}", title)))
+            .collect::<HashSet<String>>();
+        for key in keys {
+            buckets.entry(key).or_default().push(i);
+        }
+    }
+
+    let mut pairs = BTreeSet::new();
+    for members in buckets.values() {
+        for (n, a) in members.iter().enumerate() {
+            for b in &members[n + 1..] {
+                pairs.insert((*a, *b));
+            }
+        }
+    }
+    pairs
+}
+
+fn find_root(parents: &mut [usize], mut x: usize) -> usize {
+    while parents[x] != x {
+        parents[x] = parents[parents[x]];
+        x = parents[x];
+    }
+    x
+}
+
+/// Scores how likely two works are the same book, shared audio files count
+/// the most, a matching title alone is not enough.
+fn compare_works(a: &Work, b: &Work) -> (f64, Vec<DuplicateReason>) {
+    let mut score = 0.0;
+    let mut reasons = vec![];
+
+    let b_fingerprints: HashSet<&String> = b.fingerprints.iter().collect();
+    if a.fingerprints.iter().any(|x| b_fingerprints.contains(x)) {
+        score += 0.6;
+        reasons.push(DuplicateReason::Fingerprint);
+    }
+    let title = normalise_name(&a.name);
+    if !title.is_empty() && title == normalise_name(&b.name) {
+        score += 0.4;
+        reasons.push(DuplicateReason::Title);
+    }
+    if same_author(a, b) {
+        score += 0.2;
+        reasons.push(DuplicateReason::Author);
+    }
+    if similar_duration(a, b) {
+        score += 0.2;
+        reasons.push(DuplicateReason::Duration);
+    }
+    (f64::min(score, 1.0), reasons)
+}
+
+fn same_author(a: &Work, b: &Work) -> bool {
+    let names = |work: &Work| -> HashSet<String> {
+        if work.authors.is_empty() {
+            HashSet::from([normalise_person(&work.author)])
+        } else {
+            work.authors
+                .iter()
+                .map(|x| normalise_person(&x.name))
+                .collect()
+        }
+    };
+    let (a, b) = (names(a), names(b));
+    a.iter().any(|x| !x.is_empty() && b.contains(x))
+}
+
+fn similar_duration(a: &Work, b: &Work) -> bool {
+    let (a, b) = (a.duration.as_secs_f64(), b.duration.as_secs_f64());
+    if a == 0.0 || b == 0.0 {
+        return false;
+    }
+    let tolerance = f64::max(a.max(b) * DURATION_TOLERANCE, MIN_DURATION_TOLERANCE);
+    (a - b).abs() <= tolerance
+}
@@ -0,0 +1,190 @@
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::library_cmds::load_library;
+use crate::sidecar::{ABS_METADATA_FILE, OPF_FILE};
+use crate::track_cache::load_track_metadata;
+use crate::types::{ExportFailure, ExportFormat, ExportReport, TrackMetadata, Work};
+use crate::utils::stored_path;
+
+/// Writes the metadata of `work_ids`, or of the whole library, as sidecar
+/// files. Without a `directory` they are written into each work's folder,
+/// where existing sidecars are skipped since scans prefer them over tags,
+/// otherwise into the work's path relative to its library root under
+/// `directory`. Audio files are never modified.
+#[tauri::command]
+pub async fn export_metadata(
+    format: ExportFormat,
+    work_ids: Option<Vec<String>>,
+    directory: Option<String>,
+) -> Result<ExportReport, String> {
+    let mut works = load_library()
+        .await
+        .map_err(|_| "failed to load library".to_owned())?;
+    if let Some(ids) = work_ids {
+        works.retain(|x| ids.contains(&x.id));
+    }
+
+    let mut report = ExportReport::default();
+    for work in works {
+        let folder = match &directory {
+            Some(directory) => export_folder(Path::new(directory), &work),
+            None => stored_path(&work.path),
+        };
+        let (file_name, contents) = match format {
+            ExportFormat::Audiobookshelf => {
+                let tracks = load_track_metadata(&work.audio_files).await;
+                (ABS_METADATA_FILE, abs_metadata(&work, &tracks.tracks))
+            }
+            ExportFormat::Opf => (OPF_FILE, opf_metadata(&work)),
+        };
+
+        let path = folder.join(file_name);
+        if directory.is_none() && path.exists() {
+            report.skipped.push(path.to_string_lossy().to_string());
+            continue;
+        }
+        match fs::create_dir_all(&folder).and_then(|_| fs::write(&path, contents)) {
+            Ok(()) => report.written.push(path.to_string_lossy().to_string()),
+            Err(err) => report.failures.push(ExportFailure {
+                path: path.to_string_lossy().to_string(),
+                message: err.to_string(),
+            }),
+        }
+    }
+    Ok(report)
+}
+
+/// Keeps the layout of the library roots inside an export directory, so
+/// works with the same folder name don't overwrite each other.
+fn export_folder(directory: &Path, work: &Work) -> PathBuf {
+    let path = stored_path(&work.path);
+    match path.strip_prefix(&work.root) {
+        Ok(relative) if !work.root.is_empty() => directory.join(relative),
+        _ => directory.join(path.file_name().unwrap_or_default()),
+    }
+}
+
+/// Audiobookshelf's `metadata.json`, chapters run across all audio files.
+fn abs_metadata(work: &Work, tracks: &[TrackMetadata]) -> String {
+    let metadata = &work.metadata;
+    let series = metadata
+        .series
+        .as_ref()
+        .map(|series| match metadata.series_index {
+            Some(index) => format!("{} #{}", series, index),
+            None => series.clone(),
+        });
+
+    let mut start = 0.0;
+    let mut chapters = vec![];
+    for (id, chapter) in tracks.iter().flat_map(|x| &x.chapters).enumerate() {
+        let end = start + chapter.length.as_secs_f64();
+        chapters.push(json!({
+            "id": id,
+            "start": start,
+            "end": end,
+            "title": chapter.title,
+        }));
+        start = end;
+    }
+
+    let json = json!({
+        "tags": [],
+        "chapters": chapters,
+        "title": work.title(),
+        "subtitle": metadata.subtitle,
+        "authors": work.author_names(),
+        "narrators": metadata.narrators,
+        "series": series.into_iter().collect::<Vec<String>>(),
+        "genres": metadata.genres,
+        "publishedYear": metadata.published_year.map(|x| x.to_string()),
+        "publishedDate": null,
+        "publisher": metadata.publisher,
+        "description": metadata.description,
+        "isbn": metadata.isbn,
+        "asin": metadata.asin,
+        "language": metadata.language,
+        "explicit": false,
+        "abridged": false,
+    });
+    serde_json::to_string_pretty(&json).unwrap_or_default()
+}
+
+/// A Calibre style OPF package, the format has no place for chapters.
+fn opf_metadata(work: &Work) -> String {
+    let metadata = &work.metadata;
+    let mut elements = vec![format!(
+        "<dc:title>{}</dc:title>",
+        escape_xml(&work.title())
+    )];
+    for author in work.author_names() {
+        elements.push(format!(
+            r#"<dc:creator opf:role="aut">{}</dc:creator>"#,
+            escape_xml(&author)
+        ));
+    }
+    for narrator in &metadata.narrators {
+        elements.push(format!(
+            r#"<dc:creator opf:role="nrt">{}</dc:creator>"#,
+            escape_xml(narrator)
+        ));
+    }
+    let optional = [
+        ("description", metadata.description.clone()),
+        ("publisher", metadata.publisher.clone()),
+        ("date", metadata.published_year.map(|x| x.to_string())),
+        ("language", metadata.language.clone()),
+        ("rights", metadata.copyright.clone()),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            elements.push(format!("<dc:{0}>{1}</dc:{0}>", name, escape_xml(&value)));
+        }
+    }
+    for genre in &metadata.genres {
+        elements.push(format!("<dc:subject>{}</dc:subject>", escape_xml(genre)));
+    }
+    if let Some(isbn) = &metadata.isbn {
+        elements.push(format!(
+            r#"<dc:identifier opf:scheme="ISBN">{}</dc:identifier>"#,
+            escape_xml(isbn)
+        ));
+    }
+    if let Some(asin) = &metadata.asin {
+        elements.push(format!(
+            r#"<dc:identifier opf:scheme="ASIN">{}</dc:identifier>"#,
+            escape_xml(asin)
+        ));
+    }
+    if let Some(series) = &metadata.series {
+        elements.push(format!(
+            r#"<meta name="calibre:series" content="{}"/>"#,
+            escape_xml(series)
+        ));
+        if let Some(index) = metadata.series_index {
+            elements.push(format!(
+                r#"<meta name="calibre:series_index" content="{}"/>"#,
+                index
+            ));
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"2.0\">\n  \
+        <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+        xmlns:opf=\"http://www.idpf.org/2007/opf\">\n    {}\n  </metadata>\n\
+        </package>\n",
+        elements.join("\n    ")
+    )
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,49 @@
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::utils::stored_path;
+
+/// Bytes hashed from the start and from the end of a file.
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Identifies an audio file by its size and a hash of its first and last
+/// bytes, so it is recognised after being moved or renamed without reading
+/// the whole file.
+pub fn fingerprint_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut hasher = Sha256::new();
+    let mut sample = vec![];
+    file.by_ref().take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    if size > SAMPLE_SIZE * 2 {
+        file.seek(SeekFrom::End(-(SAMPLE_SIZE as i64)))?;
+        file.take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    }
+    hasher.update(&sample);
+
+    let hash = hasher
+        .finalize()
+        .iter()
+        .take(16)
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    Ok(format!("{}-{}", size, hash))
+}
+
+/// Fingerprints every file that can be read, in order.
+pub fn fingerprint_files(files: &[String]) -> Vec<String> {
+    files
+        .iter()
+        .filter_map(|path| match fingerprint_file(&stored_path(path)) {
+            Ok(fingerprint) => Some(fingerprint),
+            Err(err) => {
+                warn!("Failed to fingerprint {:?}: {}", path, err);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,85 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::naming::{parse_index_tag, parse_series_index};
+use crate::types::WorkMetadata;
+use crate::utils::split_people;
+
+/// Layouts used when the settings do not define any, matching the original
+/// `Author/Title` and `Author/Series/Title` folder structure.
+pub const DEFAULT_FOLDER_TEMPLATES: [&str; 2] = ["{author}/{title}", "{author}/{series}/{title}"];
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{(\w+)\}").unwrap());
+
+/// A folder layout such as `{author}/{series}/{series_index} - {title}`,
+/// matched against folder paths relative to the library root.
+pub struct FolderTemplate {
+    pattern: Regex,
+}
+
+impl FolderTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let template = template.trim().trim_matches('/');
+        let mut pattern = String::from("^");
+        let mut placeholders: Vec<String> = vec![];
+        let mut last = 0;
+
+        for captures in PLACEHOLDER.captures_iter(template) {
+            let whole = captures.get(0).unwrap();
+            let name = captures.get(1).unwrap().as_str();
+
+            pattern.push_str(&regex::escape(&template[last..whole.start()]));
+            pattern.push_str(&match name {
+                "author" | "title" | "series" | "narrator" => format!("(?P<{name}>[^/]+?)"),
+                "series_index" => r"(?P<series_index>\d+(?:\.\d+)?)".to_owned(),
+                "year" => r"(?P<year>\d{4})".to_owned(),
+                _ => return Err(format!("unknown placeholder {{{}}}", name)),
+            });
+            if placeholders.iter().any(|x| x == name) {
+                return Err(format!("placeholder {{{}}} is used twice", name));
+            }
+            placeholders.push(name.to_owned());
+            last = whole.end();
+        }
+        pattern.push_str(&regex::escape(&template[last..]));
+        pattern.push('$');
+
+        if !placeholders.iter().any(|x| x == "title") {
+            return Err("template must contain {title}".into());
+        }
+
+        Ok(Self {
+            pattern: Regex::new(&pattern).map_err(|err| format!("invalid template: {}", err))?,
+        })
+    }
+
+    /// Interprets the folder names between the library root and a work folder.
+    pub fn match_folders(&self, folders: &[String]) -> Option<WorkMetadata> {
+        let captures = self.pattern.captures(&folders.join("/"))?;
+        let text = |name: &str| {
+            captures
+                .name(name)
+                .map(|x| x.as_str().trim().to_owned())
+                .filter(|x| !x.is_empty())
+        };
+
+        let title = text("title")?;
+        let series = text("series");
+        // without a {series_index} the index often leads the title, "03 - Title"
+        let series_index = text("series_index")
+            .and_then(|x| parse_index_tag(&x))
+            .or_else(|| series.as_ref().and_then(|_| parse_series_index(&title)));
+
+        Some(WorkMetadata {
+            title,
+            authors: text("author").map(|x| split_people(&x)).unwrap_or_default(),
+            narrators: text("narrator")
+                .map(|x| split_people(&x))
+                .unwrap_or_default(),
+            series,
+            series_index,
+            published_year: text("year").and_then(|x| x.parse().ok()),
+            ..Default::default()
+        })
+    }
+}
@@ -1,5 +1,14 @@
-use crate::types::{ClearDatabaseError, LoadWorksError, Work};
-use crate::utils::{into_iter_objects, object_into_work};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use surrealdb::sql::{thing, Value};
+
+use crate::covers::{collect_cover_garbage, cover_cache_dir};
+use crate::naming::{natural_cmp, normalise_genres, normalise_person};
+use crate::types::{
+    AuthorSummary, ClearDatabaseError, LibraryFilter, LoadWorksError, SeriesSummary, Work,
+};
+use crate::utils::{delete_work, into_iter_objects, object_into_work, WORK_FETCH, WORK_FIELDS};
 
 use crate::DB;
 use crate::SES;
@@ -8,18 +17,115 @@ use crate::SES;
 pub async fn clear_library() -> Result<(), ClearDatabaseError> {
     DB.get()
         .expect("DB does not exist")
-        .execute("REMOVE TABLE works", &SES, None, false)
+        .execute(
+            "REMOVE TABLE works; REMOVE TABLE wrote; REMOVE TABLE narrated; \
+            REMOVE TABLE merged_paths; REMOVE TABLE duplicate_ignores; \
+            REMOVE TABLE track_metadata",
+            &SES,
+            None,
+            false,
+        )
         .await
         .map(|_| ())
         .map_err(|_| ClearDatabaseError)
 }
 
+/// Removes cached covers no work uses anymore, returns how many files were
+/// deleted.
+#[tauri::command]
+pub async fn clean_cover_cache(app_handle: tauri::AppHandle) -> Result<usize, String> {
+    let Some(cache_dir) = cover_cache_dir(&app_handle) else {
+        return Err("cannot find app cache path".into());
+    };
+    let used = load_library()
+        .await
+        .map_err(|_| "failed to load library".to_owned())?
+        .into_iter()
+        .filter_map(|x| x.cover)
+        .map(|x| x.hash)
+        .collect();
+    Ok(collect_cover_garbage(&cache_dir, &used))
+}
+
+/// Share of a work that must be listened to for it to count as completed.
+const COMPLETED_THRESHOLD: f64 = 0.95;
+
 #[tauri::command]
 pub async fn load_library() -> Result<Vec<Work>, LoadWorksError> {
+    query_works(
+        format!("SELECT {WORK_FIELDS} FROM works {WORK_FETCH}"),
+        None,
+    )
+    .await
+}
+
+/// Lists the works matching every condition of `filter`.
+#[tauri::command]
+pub async fn filter_library(filter: LibraryFilter) -> Result<Vec<Work>, LoadWorksError> {
+    let mut conditions = vec![];
+    let mut data: BTreeMap<String, Value> = BTreeMap::new();
+    if let Some(codec) = filter.codec {
+        conditions.push("$codec INSIDE audio.codecs");
+        data.insert("codec".into(), codec.into());
+    }
+    if let Some(container) = filter.container {
+        conditions.push("$container INSIDE audio.containers");
+        data.insert("container".into(), container.into());
+    }
+    if let Some(bitrate) = filter.min_bitrate {
+        conditions.push("audio.min_bitrate >= $min_bitrate");
+        data.insert("min_bitrate".into(), i64::from(bitrate).into());
+    }
+    if let Some(bitrate) = filter.max_bitrate {
+        conditions.push("audio.max_bitrate <= $max_bitrate");
+        data.insert("max_bitrate".into(), i64::from(bitrate).into());
+    }
+    if let Some(channels) = filter.channels {
+        conditions.push("audio.channels = $channels");
+        data.insert("channels".into(), i64::from(channels).into());
+    }
+    if let Some(drm) = filter.drm {
+        conditions.push("audio.drm = $drm");
+        data.insert("drm".into(), drm.into());
+    }
+
+    if let Some(genre) = filter
+        .genre
+        .and_then(|x| normalise_genres(&x).into_iter().next())
+    {
+        conditions.push("$genre INSIDE metadata.genres");
+        data.insert("genre".into(), genre.into());
+    }
+    if let Some(year) = filter.min_year {
+        conditions.push("metadata.published_year >= $min_year");
+        data.insert("min_year".into(), i64::from(year).into());
+    }
+    if let Some(year) = filter.max_year {
+        conditions.push("metadata.published_year <= $max_year");
+        data.insert("max_year".into(), i64::from(year).into());
+    }
+
+    if conditions.is_empty() {
+        return load_library().await;
+    }
+    query_works(
+        format!(
+            "SELECT {WORK_FIELDS} FROM works WHERE {} {WORK_FETCH}",
+            conditions.join(" AND ")
+        ),
+        Some(data),
+    )
+    .await
+}
+
+async fn query_works(
+    ass: String,
+    data: Option<BTreeMap<String, Value>>,
+) -> Result<Vec<Work>, LoadWorksError> {
     let result = DB
         .get()
         .expect("DB does not exist")
-        .execute("SELECT * FROM works FETCH author", &SES, None, false)
+        .execute(ass.as_str(), &SES, data, false)
         .await
         .map_err(|_| LoadWorksError)?;
 
@@ -31,12 +137,22 @@ pub async fn load_library() -> Result<Vec<Work>, LoadWorksError> {
         .collect())
 }
 
+/// Lists the works the hybrid scanner flagged for review.
+#[tauri::command]
+pub async fn load_review_works() -> Result<Vec<Work>, LoadWorksError> {
+    query_works(
+        format!("SELECT {WORK_FIELDS} FROM works WHERE array::len(review) > 0 {WORK_FETCH}"),
+        None,
+    )
+    .await
+}
+
 #[tauri::command]
 pub async fn search(search: String) -> Vec<Work> {
     let ass = format!(
-        "SELECT * FROM works WHERE \
+        "SELECT {WORK_FIELDS} FROM works WHERE \
             string::lowercase(name) CONTAINS string::lowercase('{search}') \
-            FETCH author"
+            {WORK_FETCH}"
     ); // string::lowercase(author->name) CONTAINS string::lowercase('{search}') \ COALESCE(string::lowercase(series),'') CONTAINS string::lowercase('{search}') \
     let result = DB
         .get()
@@ -69,6 +185,30 @@ pub async fn library_stats() {
     // todo: enable stats for about page
 }
 
+/// Works whose files vanished from their library root, see
+/// `utils::mark_missing_works`.
+#[tauri::command]
+pub async fn load_missing_works() -> Result<Vec<Work>, LoadWorksError> {
+    query_works(
+        format!("SELECT {WORK_FIELDS} FROM works WHERE missing = true {WORK_FETCH}"),
+        None,
+    )
+    .await
+}
+
+/// Deletes the works flagged as missing together with their listening times
+/// and author links, returns how many were removed.
+#[tauri::command]
+pub async fn remove_missing_works() -> Result<usize, ClearDatabaseError> {
+    let missing = load_missing_works().await.map_err(|_| ClearDatabaseError)?;
+    for work in missing.iter() {
+        delete_work(&work.id)
+            .await
+            .map_err(|_| ClearDatabaseError)?;
+    }
+    Ok(missing.len())
+}
+
 #[tauri::command]
 pub async fn clear_times() -> Result<(), ClearDatabaseError> {
     DB.get()
@@ -78,3 +218,186 @@ pub async fn clear_times() -> Result<(), ClearDatabaseError> {
         .map(|_| ())
         .map_err(|_| ClearDatabaseError)
 }
+
+#[tauri::command]
+pub async fn load_authors() -> Result<Vec<AuthorSummary>, LoadWorksError> {
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute(
+            "SELECT id, name, count(->wrote->works) AS book_count, \
+                math::sum(->wrote->works.duration) AS duration \
+                FROM authors WHERE count(->wrote->works) > 0 ORDER BY name",
+            &SES,
+            None,
+            false,
+        )
+        .await
+        .map_err(|_| LoadWorksError)?;
+
+    let objects = into_iter_objects(result).map_err(|_| LoadWorksError)?;
+
+    Ok(objects
+        .filter_map(Result::ok)
+        .map(|object| AuthorSummary {
+            id: object.get("id").map(|x| x.clone().as_string()).unwrap(),
+            name: object.get("name").map(|x| x.clone().as_string()).unwrap(),
+            book_count: object
+                .get("book_count")
+                .map(|x| x.clone().as_int() as usize)
+                .unwrap_or_default(),
+            duration: object
+                .get("duration")
+                .map(|x| Duration::from_secs_f64(x.clone().as_float().max(0.0)))
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn load_author_works(author_id: String) -> Result<Vec<Work>, LoadWorksError> {
+    let author = thing(&author_id).map_err(|_| LoadWorksError)?;
+    if author.tb != "authors" {
+        return Err(LoadWorksError);
+    }
+    let data: BTreeMap<String, Value> = BTreeMap::from([("author".into(), author.into())]);
+    let mut works = query_works(
+        format!(
+            "SELECT {WORK_FIELDS} FROM works WHERE <-wrote<-authors CONTAINS $author {WORK_FETCH}"
+        ),
+        Some(data),
+    )
+    .await?;
+    works.sort_by(|a, b| a.series.cmp(&b.series).then_with(|| compare_volumes(a, b)));
+    Ok(works)
+}
+
+#[tauri::command]
+pub async fn load_series() -> Result<Vec<SeriesSummary>, LoadWorksError> {
+    let works = query_works(
+        format!("SELECT {WORK_FIELDS} FROM works WHERE string::length(series) > 0 {WORK_FETCH}"),
+        None,
+    )
+    .await?;
+    let positions = load_positions().await?;
+
+    // series by different authors can share a name
+    let mut series: BTreeMap<(String, String), Vec<Work>> = BTreeMap::new();
+    for work in works {
+        series
+            .entry((
+                work.series.clone().unwrap_or_default(),
+                series_author(&work),
+            ))
+            .or_default()
+            .push(work);
+    }
+
+    Ok(series
+        .into_iter()
+        .map(|((name, _), volumes)| summarise_series(name, volumes, &positions))
+        .collect())
+}
+
+/// Volumes of a series, limited to the ones by `author` when given since
+/// series by different authors can share a name.
+#[tauri::command]
+pub async fn load_series_works(
+    series: String,
+    author: Option<String>,
+) -> Result<Vec<Work>, LoadWorksError> {
+    let data: BTreeMap<String, Value> = BTreeMap::from([("series".into(), series.into())]);
+    let mut works = query_works(
+        format!("SELECT {WORK_FIELDS} FROM works WHERE series = $series {WORK_FETCH}"),
+        Some(data),
+    )
+    .await?;
+    if let Some(author) = author {
+        let author = normalise_person(&author);
+        works.retain(|x| series_author(x) == author);
+    }
+    sort_volumes(&mut works);
+    Ok(works)
+}
+
+/// The first author of a work, normalised to tell series apart.
+fn series_author(work: &Work) -> String {
+    normalise_person(work.authors.first().map_or(&work.author, |x| &x.name))
+}
+
+fn sort_volumes(volumes: &mut [Work]) {
+    volumes.sort_by(compare_volumes);
+}
+
+/// Orders volumes by series index, volumes without one go last by name.
+fn compare_volumes(a: &Work, b: &Work) -> Ordering {
+    match (a.series_index, b.series_index) {
+        (Some(x), Some(y)) => x.total_cmp(&y).then_with(|| natural_cmp(&a.name, &b.name)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => natural_cmp(&a.name, &b.name),
+    }
+}
+
+fn summarise_series(
+    name: String,
+    mut volumes: Vec<Work>,
+    positions: &HashMap<String, f64>,
+) -> SeriesSummary {
+    sort_volumes(&mut volumes);
+
+    let mut authors: Vec<String> = vec![];
+    for author in volumes.iter().flat_map(|x| x.authors.iter()) {
+        if !authors.contains(&author.name) {
+            authors.push(author.name.clone());
+        }
+    }
+
+    let progress_of = |work: &Work| {
+        let duration = work.duration.as_secs_f64();
+        match positions.get(&work.id) {
+            Some(position) if duration > 0.0 => (position / duration).clamp(0.0, 1.0),
+            _ => 0.0,
+        }
+    };
+    let completed = volumes
+        .iter()
+        .filter(|x| progress_of(x) >= COMPLETED_THRESHOLD)
+        .count();
+    let progress = if volumes.is_empty() {
+        0.0
+    } else {
+        volumes.iter().map(progress_of).sum::<f64>() / volumes.len() as f64
+    };
+
+    SeriesSummary {
+        name,
+        authors,
+        volumes,
+        completed,
+        progress,
+    }
+}
+
+/// Loads the last saved position of every work, keyed by work id.
+pub async fn load_positions() -> Result<HashMap<String, f64>, LoadWorksError> {
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute("SELECT work, position FROM times", &SES, None, false)
+        .await
+        .map_err(|_| LoadWorksError)?;
+
+    let objects = into_iter_objects(result).map_err(|_| LoadWorksError)?;
+
+    // works saved before positions were replaced can still have several
+    let mut positions: HashMap<String, f64> = HashMap::new();
+    for object in objects.filter_map(Result::ok) {
+        let (Some(work), Some(position)) = (object.get("work"), object.get("position")) else {
+            continue;
+        };
+        let furthest = positions.entry(work.clone().as_string()).or_insert(0.0);
+        *furthest = furthest.max(position.clone().as_float());
+    }
+    Ok(positions)
+}
@@ -16,11 +16,24 @@ use surrealdb::kvs::Datastore;
 use tauri::Manager;
 use window_shadows::set_shadow;
 
+mod backup_cmds;
 mod book_cmds;
+mod covers;
+mod duplicate_cmds;
+mod export_cmds;
+mod fingerprint;
+mod folder_template;
 mod library_cmds;
+mod match_cmds;
+mod metadata_providers;
+mod naming;
 mod player_cmds;
+mod progress_cmds;
 mod scan_cmds;
+mod scan_progress;
 mod settings_cmds;
+mod sidecar;
+mod track_cache;
 mod types;
 mod utils;
 
@@ -34,21 +47,50 @@ async fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_window_state::Builder::default().build())
         .invoke_handler(tauri::generate_handler![
+            backup_cmds::backup_library,
+            backup_cmds::restore_library,
             book_cmds::clear_book_time,
+            book_cmds::dismiss_work_review,
             book_cmds::load_book_time,
             book_cmds::load_work_metadata,
             book_cmds::load_work,
+            book_cmds::reorder_work_files,
+            book_cmds::reset_work_file_order,
             book_cmds::start_book,
+            book_cmds::update_work_metadata,
             book_cmds::update_work_time,
+            duplicate_cmds::find_duplicates,
+            duplicate_cmds::ignore_duplicates,
+            duplicate_cmds::merge_duplicates,
+            export_cmds::export_metadata,
+            library_cmds::clean_cover_cache,
             library_cmds::clear_library,
             library_cmds::clear_times,
+            library_cmds::filter_library,
             library_cmds::library_stats,
+            library_cmds::load_author_works,
+            library_cmds::load_authors,
             library_cmds::load_library,
+            library_cmds::load_missing_works,
+            library_cmds::load_review_works,
+            library_cmds::load_series,
+            library_cmds::load_series_works,
+            library_cmds::remove_missing_works,
             library_cmds::search,
+            match_cmds::match_book,
+            match_cmds::search_book_metadata,
             player_cmds::pause,
             player_cmds::play,
             player_cmds::stop,
+            progress_cmds::export_progress,
+            progress_cmds::import_progress,
+            scan_cmds::cancel_scan,
+            scan_cmds::load_scan_problem_files,
+            scan_cmds::load_scan_reports,
+            scan_cmds::preview_folder_template,
             scan_cmds::scan_folder,
+            scan_cmds::scan_hybrid,
+            scan_cmds::scan_library,
             scan_cmds::scan_metadata,
             settings_cmds::load_settings,
             settings_cmds::save_settings,
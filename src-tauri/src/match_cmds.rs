@@ -0,0 +1,100 @@
+use log::warn;
+use std::collections::BTreeMap;
+use surrealdb::sql::Value;
+use tauri::AppHandle;
+
+use crate::book_cmds::{load_work, update_work_metadata};
+use crate::covers::{cache_downloaded_cover, cover_cache_dir};
+use crate::metadata_providers::{
+    configured_providers, search_providers, DbResponseCache, HttpClient, TauriHttpClient,
+};
+use crate::settings_cmds::load_settings;
+use crate::types::{CoverArt, MetadataCandidate, MetadataQuery, Work};
+use crate::utils::to_value;
+use crate::DB;
+use crate::SES;
+
+/// Looks a work up with the online metadata providers. Without a `query` the
+/// work's ISBN, or else its title and first author, are searched for.
+#[tauri::command]
+pub async fn search_book_metadata(
+    app_handle: AppHandle,
+    work_id: String,
+    query: Option<MetadataQuery>,
+) -> Result<Vec<MetadataCandidate>, String> {
+    let settings = load_settings(app_handle).await?;
+    let query = match query {
+        Some(query) => query,
+        None => {
+            let work = load_work(work_id)
+                .await
+                .map_err(|_| "failed to load work".to_owned())?;
+            work_query(&work)
+        }
+    };
+
+    let providers = configured_providers(&settings.metadata_providers);
+    if providers.is_empty() {
+        return Err("no metadata provider configured".into());
+    }
+    Ok(search_providers(&TauriHttpClient, &DbResponseCache, &providers, &query).await)
+}
+
+/// Applies a candidate picked from `search_book_metadata` to a work. Its
+/// values are locked like manual edits, fields the user already locked are
+/// kept.
+#[tauri::command]
+pub async fn match_book(
+    app_handle: AppHandle,
+    work_id: String,
+    candidate: MetadataCandidate,
+) -> Result<Work, String> {
+    let work = load_work(work_id.clone())
+        .await
+        .map_err(|_| "failed to load work".to_owned())?;
+
+    let mut metadata = work.metadata.clone();
+    metadata.merge_scanned(candidate.metadata);
+    let work = update_work_metadata(work_id.clone(), metadata).await?;
+
+    let (Some(url), Some(cache_dir)) = (candidate.cover_url, cover_cache_dir(&app_handle)) else {
+        return Ok(work);
+    };
+    let cover = match TauriHttpClient.get(&url).await {
+        Ok(data) => cache_downloaded_cover(&cache_dir, data, &url),
+        Err(err) => {
+            warn!("Failed to download cover: {}", err);
+            None
+        }
+    };
+    if let Some(cover) = cover {
+        save_work_cover(&work_id, &cover).await?;
+    }
+
+    load_work(work_id)
+        .await
+        .map_err(|_| "failed to load work".into())
+}
+
+fn work_query(work: &Work) -> MetadataQuery {
+    MetadataQuery {
+        title: Some(work.title()),
+        author: work.author_names().into_iter().next(),
+        isbn: work.metadata.isbn.clone(),
+    }
+}
+
+async fn save_work_cover(work_id: &str, cover: &CoverArt) -> Result<(), String> {
+    let data: BTreeMap<String, Value> = BTreeMap::from([("cover".into(), to_value(cover)?)]);
+    DB.get()
+        .expect("DB does not exist")
+        .execute(
+            format!("UPDATE {} SET cover = $cover", work_id).as_str(),
+            &SES,
+            Some(data),
+            false,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("failed to save cover: {}", err))
+}
@@ -0,0 +1,683 @@
+use log::{error, warn};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use surrealdb::sql::Value;
+use tauri::api::http::{ClientBuilder, HttpRequestBuilder, ResponseType};
+
+use crate::naming::{normalise_genres, parse_year};
+use crate::types::{MetadataCandidate, MetadataProviderSettings, MetadataQuery, WorkMetadata};
+use crate::utils::into_iter_objects;
+use crate::DB;
+use crate::SES;
+
+/// Candidates asked from each provider.
+const MAX_CANDIDATES: usize = 5;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// Provider responses are reused for a week before being fetched again.
+const CACHE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub type HttpFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, String>> + Send + 'a>>;
+
+/// Fetches the body of a URL, swapped for a stub when testing providers.
+pub trait HttpClient: Send + Sync {
+    fn get<'a>(&'a self, url: &'a str) -> HttpFuture<'a>;
+}
+
+pub struct TauriHttpClient;
+
+impl HttpClient for TauriHttpClient {
+    fn get<'a>(&'a self, url: &'a str) -> HttpFuture<'a> {
+        Box::pin(async move {
+            let client = ClientBuilder::new()
+                .max_redirections(5)
+                .build()
+                .map_err(|err| format!("failed to create http client: {}", err))?;
+            let request = HttpRequestBuilder::new("GET", url)
+                .map_err(|err| format!("invalid url {}: {}", url, err))?
+                .timeout(REQUEST_TIMEOUT)
+                .response_type(ResponseType::Binary);
+            let response = client
+                .send(request)
+                .await
+                .map_err(|err| format!("failed to fetch {}: {}", url, err))?;
+            if !response.status().is_success() {
+                return Err(format!("{} returned {}", url, response.status()));
+            }
+            response
+                .bytes()
+                .await
+                .map(|x| x.data)
+                .map_err(|err| format!("failed to read {}: {}", url, err))
+        })
+    }
+}
+
+/// An online catalogue books can be looked up in. Providers only build URLs
+/// and read responses, fetching and caching is left to `search_providers`.
+pub trait MetadataProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `None` when the query has nothing the provider can search by.
+    fn search_url(&self, query: &MetadataQuery) -> Option<String>;
+
+    fn parse_search(&self, json: &JsonValue) -> Vec<MetadataCandidate>;
+
+    /// A second request for details the search results leave out.
+    fn details_url(&self, _candidate: &MetadataCandidate) -> Option<String> {
+        None
+    }
+
+    fn parse_details(&self, _candidate: &mut MetadataCandidate, _json: &JsonValue) {}
+}
+
+/// The providers with a base URL configured.
+pub fn configured_providers(settings: &MetadataProviderSettings) -> Vec<Box<dyn MetadataProvider>> {
+    let mut providers: Vec<Box<dyn MetadataProvider>> = vec![];
+    if !settings.open_library_url.is_empty() {
+        providers.push(Box::new(OpenLibrary {
+            base_url: settings.open_library_url.trim_end_matches('/').to_owned(),
+            covers_url: settings
+                .open_library_covers_url
+                .trim_end_matches('/')
+                .to_owned(),
+        }));
+    }
+    if !settings.google_books_url.is_empty() {
+        providers.push(Box::new(GoogleBooks {
+            base_url: settings.google_books_url.trim_end_matches('/').to_owned(),
+        }));
+    }
+    providers
+}
+
+/// Asks every provider for candidates, a provider failing only loses its own
+/// candidates.
+pub async fn search_providers(
+    client: &dyn HttpClient,
+    cache: &dyn ResponseCache,
+    providers: &[Box<dyn MetadataProvider>],
+    query: &MetadataQuery,
+) -> Vec<MetadataCandidate> {
+    let mut candidates = vec![];
+    for provider in providers {
+        let Some(url) = provider.search_url(query) else {
+            continue;
+        };
+        let found = match fetch_json(client, cache, &url).await {
+            Ok(json) => provider.parse_search(&json),
+            Err(err) => {
+                warn!("{} search failed: {}", provider.name(), err);
+                continue;
+            }
+        };
+
+        for mut candidate in found.into_iter().take(MAX_CANDIDATES) {
+            if let Some(url) = provider.details_url(&candidate) {
+                match fetch_json(client, cache, &url).await {
+                    Ok(json) => provider.parse_details(&mut candidate, &json),
+                    Err(err) => warn!("{} details failed: {}", provider.name(), err),
+                }
+            }
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+/// Fetches a JSON response, reusing a cached copy younger than
+/// `CACHE_MAX_AGE`.
+async fn fetch_json(
+    client: &dyn HttpClient,
+    cache: &dyn ResponseCache,
+    url: &str,
+) -> Result<JsonValue, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    match cache.load(url).await {
+        Ok(Some((fetched_at, body)))
+            if now.saturating_sub(fetched_at) < CACHE_MAX_AGE.as_secs() =>
+        {
+            if let Ok(json) = serde_json::from_str(&body) {
+                return Ok(json);
+            }
+        }
+        Ok(_) => {}
+        Err(err) => error!("{}", err),
+    }
+
+    let body = client.get(url).await?;
+    let body = String::from_utf8(body).map_err(|_| format!("{} did not return text", url))?;
+    let json = serde_json::from_str(&body)
+        .map_err(|err| format!("{} did not return JSON: {}", url, err))?;
+    if let Err(err) = cache.save(url, now, &body).await {
+        error!("{}", err);
+    }
+    Ok(json)
+}
+
+pub type CacheFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+/// Keeps provider responses by URL along with when they were fetched.
+pub trait ResponseCache: Send + Sync {
+    fn load<'a>(&'a self, url: &'a str) -> CacheFuture<'a, Option<(u64, String)>>;
+
+    fn save<'a>(&'a self, url: &'a str, fetched_at: u64, body: &'a str) -> CacheFuture<'a, ()>;
+}
+
+/// Caches responses in the `metadata_cache` table.
+pub struct DbResponseCache;
+
+impl ResponseCache for DbResponseCache {
+    fn load<'a>(&'a self, url: &'a str) -> CacheFuture<'a, Option<(u64, String)>> {
+        Box::pin(async move {
+            let data: BTreeMap<String, Value> = BTreeMap::from([("url".into(), url.into())]);
+            let result = DB
+                .get()
+                .expect("DB does not exist")
+                .execute(
+                    "SELECT fetched_at, body FROM metadata_cache WHERE url = $url",
+                    &SES,
+                    Some(data),
+                    false,
+                )
+                .await
+                .map_err(|err| format!("failed to load cached response: {}", err))?;
+
+            Ok(into_iter_objects(result)?
+                .filter_map(Result::ok)
+                .next()
+                .map(|object| {
+                    (
+                        object
+                            .get("fetched_at")
+                            .map(|x| x.clone().as_float() as u64)
+                            .unwrap_or_default(),
+                        object
+                            .get("body")
+                            .map(|x| x.clone().as_string())
+                            .unwrap_or_default(),
+                    )
+                }))
+        })
+    }
+
+    fn save<'a>(&'a self, url: &'a str, fetched_at: u64, body: &'a str) -> CacheFuture<'a, ()> {
+        Box::pin(async move {
+            let data: BTreeMap<String, Value> = BTreeMap::from([
+                ("url".into(), url.into()),
+                ("fetched_at".into(), (fetched_at as i64).into()),
+                ("body".into(), body.into()),
+            ]);
+            DB.get()
+                .expect("DB does not exist")
+                .execute(
+                    "DELETE metadata_cache WHERE url = $url; \
+                    CREATE metadata_cache SET url = $url, fetched_at = $fetched_at, body = $body",
+                    &SES,
+                    Some(data),
+                    false,
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| format!("failed to cache response: {}", err))
+        })
+    }
+}
+
+/// Open Library's search API, descriptions come from the work records.
+pub struct OpenLibrary {
+    pub base_url: String,
+    pub covers_url: String,
+}
+
+impl MetadataProvider for OpenLibrary {
+    fn name(&self) -> &'static str {
+        "Open Library"
+    }
+
+    fn search_url(&self, query: &MetadataQuery) -> Option<String> {
+        let mut parameters = vec![];
+        if let Some(isbn) = non_empty(&query.isbn) {
+            parameters.push(format!("isbn={}", encode_query(isbn)));
+        } else {
+            parameters.push(format!("title={}", encode_query(non_empty(&query.title)?)));
+            if let Some(author) = non_empty(&query.author) {
+                parameters.push(format!("author={}", encode_query(author)));
+            }
+        }
+        parameters.push(format!("limit={}", MAX_CANDIDATES));
+        Some(format!(
+            "{}/search.json?{}",
+            self.base_url,
+            parameters.join("&")
+        ))
+    }
+
+    fn parse_search(&self, json: &JsonValue) -> Vec<MetadataCandidate> {
+        let Some(docs) = json.get("docs").and_then(|x| x.as_array()) else {
+            return vec![];
+        };
+
+        docs.iter()
+            .filter_map(|doc| {
+                let id = json_string(doc, "key")?;
+                let metadata = WorkMetadata {
+                    title: json_string(doc, "title")?,
+                    subtitle: json_string(doc, "subtitle"),
+                    authors: json_strings(doc, "author_name"),
+                    published_year: doc
+                        .get("first_publish_year")
+                        .and_then(|x| x.as_i64())
+                        .map(|x| x as i32),
+                    publisher: json_strings(doc, "publisher").into_iter().next(),
+                    language: json_strings(doc, "language").into_iter().next(),
+                    isbn: json_strings(doc, "isbn").into_iter().next(),
+                    genres: normalise_genres(
+                        &json_strings(doc, "subject")
+                            .into_iter()
+                            .take(5)
+                            .collect::<Vec<String>>()
+                            .join(";"),
+                    ),
+                    series: json_strings(doc, "series").into_iter().next(),
+                    ..Default::default()
+                };
+                let cover_url = doc
+                    .get("cover_i")
+                    .and_then(|x| x.as_i64())
+                    .map(|x| format!("{}/b/id/{}-L.jpg", self.covers_url, x));
+
+                Some(MetadataCandidate {
+                    provider: self.name().to_owned(),
+                    id,
+                    metadata,
+                    cover_url,
+                })
+            })
+            .collect()
+    }
+
+    fn details_url(&self, candidate: &MetadataCandidate) -> Option<String> {
+        candidate
+            .id
+            .starts_with("/works/")
+            .then(|| format!("{}{}.json", self.base_url, candidate.id))
+    }
+
+    fn parse_details(&self, candidate: &mut MetadataCandidate, json: &JsonValue) {
+        // either a plain string or `{ "type": "/type/text", "value": "..." }`
+        let description = json.get("description").and_then(|x| {
+            x.as_str()
+                .or_else(|| x.get("value").and_then(|x| x.as_str()))
+        });
+        if let Some(description) = description {
+            candidate.metadata.description = Some(description.trim().to_owned());
+        }
+        if candidate.metadata.series.is_none() {
+            candidate.metadata.series = json_strings(json, "series").into_iter().next();
+        }
+    }
+}
+
+/// The Google Books volumes API.
+pub struct GoogleBooks {
+    pub base_url: String,
+}
+
+impl MetadataProvider for GoogleBooks {
+    fn name(&self) -> &'static str {
+        "Google Books"
+    }
+
+    fn search_url(&self, query: &MetadataQuery) -> Option<String> {
+        let terms = match non_empty(&query.isbn) {
+            Some(isbn) => format!("isbn:{}", isbn),
+            None => {
+                let mut terms = format!("intitle:{}", non_empty(&query.title)?);
+                if let Some(author) = non_empty(&query.author) {
+                    terms.push_str(&format!(" inauthor:{}", author));
+                }
+                terms
+            }
+        };
+        Some(format!(
+            "{}/volumes?q={}&maxResults={}",
+            self.base_url,
+            encode_query(&terms),
+            MAX_CANDIDATES
+        ))
+    }
+
+    fn parse_search(&self, json: &JsonValue) -> Vec<MetadataCandidate> {
+        let Some(items) = json.get("items").and_then(|x| x.as_array()) else {
+            return vec![];
+        };
+
+        items
+            .iter()
+            .filter_map(|item| {
+                let id = json_string(item, "id")?;
+                let volume = item.get("volumeInfo")?;
+                let identifier = |kind: &str| {
+                    volume
+                        .get("industryIdentifiers")
+                        .and_then(|x| x.as_array())
+                        .and_then(|x| {
+                            x.iter()
+                                .find(|x| json_string(x, "type").as_deref() == Some(kind))
+                        })
+                        .and_then(|x| json_string(x, "identifier"))
+                };
+                let metadata = WorkMetadata {
+                    title: json_string(volume, "title")?,
+                    subtitle: json_string(volume, "subtitle"),
+                    authors: json_strings(volume, "authors"),
+                    description: json_string(volume, "description"),
+                    publisher: json_string(volume, "publisher"),
+                    published_year: json_string(volume, "publishedDate")
+                        .and_then(|x| parse_year(&x)),
+                    language: json_string(volume, "language"),
+                    isbn: identifier("ISBN_13").or_else(|| identifier("ISBN_10")),
+                    genres: normalise_genres(&json_strings(volume, "categories").join(";")),
+                    ..Default::default()
+                };
+                let cover_url = volume
+                    .get("imageLinks")
+                    .and_then(|x| json_string(x, "thumbnail"))
+                    .map(|x| x.replacen("http://", "https://", 1));
+
+                Some(MetadataCandidate {
+                    provider: self.name().to_owned(),
+                    id,
+                    metadata,
+                    cover_url,
+                })
+            })
+            .collect()
+    }
+}
+
+fn json_string(json: &JsonValue, key: &str) -> Option<String> {
+    json.get(key)
+        .and_then(|x| x.as_str())
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(str::to_owned)
+}
+
+fn json_strings(json: &JsonValue, key: &str) -> Vec<String> {
+    json.get(key)
+        .and_then(|x| x.as_array())
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|x| x.as_str())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|x| !x.is_empty())
+}
+
+/// Percent-encodes a query string value.
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (x as char).to_string()
+            }
+            _ => format!("%{:02X}", x),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const OPEN_LIBRARY_SEARCH: &str = r#"{
+  "numFound": 1,
+  "docs": [
+    {
+      "key": "/works/OL27448W",
+      "title": "The Lord of the Rings",
+      "author_name": ["J.R.R. Tolkien"],
+      "first_publish_year": 1954,
+      "publisher": ["Allen & Unwin"],
+      "language": ["eng"],
+      "isbn": ["9780261103252"],
+      "subject": ["fantasy", "middle earth", "sci-fi"],
+      "cover_i": 14625765
+    }
+  ]
+}"#;
+
+    const OPEN_LIBRARY_WORK: &str = r#"{
+  "key": "/works/OL27448W",
+  "description": { "type": "/type/text", "value": " The One Ring must be destroyed. " },
+  "series": ["Middle-earth"]
+}"#;
+
+    const GOOGLE_BOOKS_VOLUMES: &str = r#"{
+  "totalItems": 1,
+  "items": [
+    {
+      "id": "aWZzLPhY4o0C",
+      "volumeInfo": {
+        "title": "The Fellowship of the Ring",
+        "subtitle": "Being the First Part of The Lord of the Rings",
+        "authors": ["J.R.R. Tolkien"],
+        "publisher": "HarperCollins",
+        "publishedDate": "2012-02-15",
+        "description": "The first volume.",
+        "industryIdentifiers": [
+          { "type": "ISBN_10", "identifier": "0547951949" },
+          { "type": "ISBN_13", "identifier": "9780547951942" }
+        ],
+        "categories": ["Fiction / Fantasy / Epic"],
+        "language": "en",
+        "imageLinks": { "thumbnail": "http://books.google.com/books/content?id=aWZzLPhY4o0C" }
+      }
+    }
+  ]
+}"#;
+
+    /// Serves `routes` by path on a local port, returns its base URL and how
+    /// many requests it answered.
+    fn stub_server(routes: HashMap<&'static str, &'static str>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap_or(0) > 2 {
+                    header.clear();
+                }
+
+                counter.fetch_add(1, Ordering::SeqCst);
+                let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let path = target.split('?').next().unwrap_or_default();
+                let response = match routes.get(path) {
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                        Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+                        Connection: close\r\n\r\n"
+                        .to_owned(),
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (base_url, requests)
+    }
+
+    #[derive(Default)]
+    struct MemoryCache(Mutex<HashMap<String, (u64, String)>>);
+
+    impl ResponseCache for MemoryCache {
+        fn load<'a>(&'a self, url: &'a str) -> CacheFuture<'a, Option<(u64, String)>> {
+            Box::pin(async move { Ok(self.0.lock().unwrap().get(url).cloned()) })
+        }
+
+        fn save<'a>(&'a self, url: &'a str, fetched_at: u64, body: &'a str) -> CacheFuture<'a, ()> {
+            Box::pin(async move {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .insert(url.to_owned(), (fetched_at, body.to_owned()));
+                Ok(())
+            })
+        }
+    }
+
+    fn query(title: &str, author: &str) -> MetadataQuery {
+        MetadataQuery {
+            title: Some(title.into()),
+            author: Some(author.into()),
+            isbn: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_open_library() {
+        let (base_url, _) = stub_server(HashMap::from([
+            ("/search.json", OPEN_LIBRARY_SEARCH),
+            ("/works/OL27448W.json", OPEN_LIBRARY_WORK),
+        ]));
+        let providers: Vec<Box<dyn MetadataProvider>> = vec![Box::new(OpenLibrary {
+            base_url,
+            covers_url: "https://covers.example.org".into(),
+        })];
+
+        let candidates = search_providers(
+            &TauriHttpClient,
+            &MemoryCache::default(),
+            &providers,
+            &query("The Lord of the Rings", "Tolkien"),
+        )
+        .await;
+
+        assert_eq!(candidates.len(), 1);
+        let candidate = &candidates[0];
+        assert_eq!(candidate.provider, "Open Library");
+        assert_eq!(candidate.id, "/works/OL27448W");
+        assert_eq!(
+            candidate.cover_url.as_deref(),
+            Some("https://covers.example.org/b/id/14625765-L.jpg")
+        );
+        let metadata = &candidate.metadata;
+        assert_eq!(metadata.title, "The Lord of the Rings");
+        assert_eq!(metadata.authors, vec!["J.R.R. Tolkien"]);
+        assert_eq!(metadata.published_year, Some(1954));
+        assert_eq!(metadata.publisher.as_deref(), Some("Allen & Unwin"));
+        assert_eq!(metadata.isbn.as_deref(), Some("9780261103252"));
+        assert_eq!(metadata.genres, vec!["Fantasy", "Middle Earth", "Sci-Fi"]);
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("The One Ring must be destroyed.")
+        );
+        assert_eq!(metadata.series.as_deref(), Some("Middle-earth"));
+    }
+
+    #[tokio::test]
+    async fn searches_google_books() {
+        let (base_url, _) = stub_server(HashMap::from([("/volumes", GOOGLE_BOOKS_VOLUMES)]));
+        let providers: Vec<Box<dyn MetadataProvider>> = vec![Box::new(GoogleBooks { base_url })];
+
+        let candidates = search_providers(
+            &TauriHttpClient,
+            &MemoryCache::default(),
+            &providers,
+            &query("The Fellowship of the Ring", "Tolkien"),
+        )
+        .await;
+
+        assert_eq!(candidates.len(), 1);
+        let candidate = &candidates[0];
+        assert_eq!(candidate.provider, "Google Books");
+        assert_eq!(candidate.id, "aWZzLPhY4o0C");
+        assert_eq!(
+            candidate.cover_url.as_deref(),
+            Some("https://books.google.com/books/content?id=aWZzLPhY4o0C")
+        );
+        let metadata = &candidate.metadata;
+        assert_eq!(metadata.title, "The Fellowship of the Ring");
+        assert_eq!(
+            metadata.subtitle.as_deref(),
+            Some("Being the First Part of The Lord of the Rings")
+        );
+        assert_eq!(metadata.authors, vec!["J.R.R. Tolkien"]);
+        assert_eq!(metadata.published_year, Some(2012));
+        assert_eq!(metadata.isbn.as_deref(), Some("9780547951942"));
+        assert_eq!(metadata.language.as_deref(), Some("en"));
+        assert_eq!(metadata.description.as_deref(), Some("The first volume."));
+    }
+
+    #[tokio::test]
+    async fn reuses_cached_responses() {
+        let (base_url, requests) = stub_server(HashMap::from([
+            ("/search.json", OPEN_LIBRARY_SEARCH),
+            ("/works/OL27448W.json", OPEN_LIBRARY_WORK),
+        ]));
+        let providers: Vec<Box<dyn MetadataProvider>> = vec![Box::new(OpenLibrary {
+            base_url,
+            covers_url: String::new(),
+        })];
+        let cache = MemoryCache::default();
+        let query = query("The Lord of the Rings", "Tolkien");
+
+        let first = search_providers(&TauriHttpClient, &cache, &providers, &query).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        let second = search_providers(&TauriHttpClient, &cache, &providers, &query).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(second.len(), first.len());
+        assert_eq!(
+            second[0].metadata.description,
+            first[0].metadata.description
+        );
+    }
+
+    #[tokio::test]
+    async fn refetches_expired_responses() {
+        let (base_url, requests) = stub_server(HashMap::from([("/volumes", GOOGLE_BOOKS_VOLUMES)]));
+        let provider = GoogleBooks { base_url };
+        let query = query("The Fellowship of the Ring", "Tolkien");
+        let url = provider.search_url(&query).unwrap();
+        let cache = MemoryCache::default();
+        cache.save(&url, 0, r#"{"items": []}"#).await.unwrap();
+
+        let providers: Vec<Box<dyn MetadataProvider>> = vec![Box::new(provider)];
+        let candidates = search_providers(&TauriHttpClient, &cache, &providers, &query).await;
+
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(candidates.len(), 1);
+    }
+}
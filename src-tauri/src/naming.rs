@@ -0,0 +1,170 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// "Book 3 - Title", "Vol. 2.5", "Part 03", "#4". A bare "No." is left out,
+/// it is part of titles such as "The No. 1 Ladies' Detective Agency".
+static MARKED_INDEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(?:\b(?:book|bk|vol|volume|part|number)\.?|#)\s*(\d+(?:\.\d+)?)\b").unwrap()
+});
+/// "01 - Title", "2. Title", "03_Title"
+static LEADING_INDEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*(\d+(?:\.\d+)?)\s*[-._)\]]\s*\S").unwrap());
+/// "Title (Book 2)" is handled above, this catches "Title (2)" and "Title [3]"
+static BRACKETED_INDEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[(\[]\s*(\d+(?:\.\d+)?)\s*[)\]]\s*$").unwrap());
+
+/// "CD1", "Disc 2", "Disk 1 - Title"
+static LEADING_DISC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^(?:cd|disc|disk)[\s._-]*(\d+)\b").unwrap());
+/// "Part 03", "Pt. 2", but not "Part 1 - Title" which names a volume
+static PART_DISC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^(?:part|pt)\.?[\s._-]*(\d+)\s*$").unwrap());
+/// "Title CD2", "Title - Disc 1"
+static TRAILING_DISC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)[\s._-](?:cd|disc|disk)[\s._-]*(\d+)\s*$").unwrap());
+
+/// Separators between the genres of a single tag value, "Fantasy; Horror"
+static GENRE_SEPARATOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"[;/,|\x00]").unwrap());
+/// ID3v1 genre references such as "(12)" left in by old taggers
+static GENRE_REFERENCE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\(\d+\)\s*").unwrap());
+
+static YEAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d{4})\b").unwrap());
+
+/// Finds the position of a volume within its series from a folder or file name.
+/// Only meaningful once the work is known to be part of a series, "2001. A
+/// Space Odyssey" would otherwise be volume 2001.
+pub fn parse_series_index(name: &str) -> Option<f64> {
+    [&MARKED_INDEX, &LEADING_INDEX, &BRACKETED_INDEX]
+        .iter()
+        .find_map(|pattern| pattern.captures(name))
+        .and_then(|captures| captures.get(1))
+        .and_then(|index| index.as_str().parse::<f64>().ok())
+}
+
+/// Reads the disc number from a folder name that marks one disc of a rip.
+pub fn parse_disc_number(name: &str) -> Option<u32> {
+    [&LEADING_DISC, &PART_DISC, &TRAILING_DISC]
+        .iter()
+        .find_map(|pattern| pattern.captures(name))
+        .and_then(|captures| captures.get(1))
+        .and_then(|disc| disc.as_str().parse::<u32>().ok())
+}
+
+/// Reads a series index from tag values such as "3", "2.5" or "3/12".
+pub fn parse_index_tag(value: &str) -> Option<f64> {
+    value
+        .split('/')
+        .next()
+        .and_then(|x| x.trim().parse::<f64>().ok())
+        .or_else(|| parse_series_index(value))
+}
+
+/// Compares names the way people expect, so "Part 2" comes before "Part 10".
+/// Runs of digits are compared by value, everything else ignoring case.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_number = take_number(&mut a_chars);
+                let y_number = take_number(&mut b_chars);
+                let ordering = x_number
+                    .trim_start_matches('0')
+                    .len()
+                    .cmp(&y_number.trim_start_matches('0').len())
+                    .then_with(|| {
+                        x_number
+                            .trim_start_matches('0')
+                            .cmp(y_number.trim_start_matches('0'))
+                    });
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+    number
+}
+
+/// Lowercases a name and drops everything but letters and digits, so
+/// spellings that differ in case, spacing or punctuation compare equal.
+pub fn normalise_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Like `normalise_name` but ignoring word order, so "Tolkien, J.R.R." and
+/// "J. R. R. Tolkien" compare equal.
+pub fn normalise_person(name: &str) -> String {
+    let mut words = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    words.sort();
+    words.concat()
+}
+
+/// Reads the year from a date tag such as "2004", "2004-05-01" or "May 2004".
+pub fn parse_year(value: &str) -> Option<i32> {
+    YEAR.captures(value)
+        .and_then(|captures| captures.get(1))
+        .and_then(|year| year.as_str().parse::<i32>().ok())
+}
+
+/// Splits a genre tag into separate genres, capitalised and without
+/// duplicates, so "sci-fi; Fantasy/fantasy" becomes "Sci-Fi" and "Fantasy".
+pub fn normalise_genres(value: &str) -> Vec<String> {
+    let mut genres: Vec<String> = vec![];
+    for genre in GENRE_SEPARATOR.split(value) {
+        let genre = GENRE_REFERENCE.replace(genre.trim(), "");
+        let genre = genre
+            .split_whitespace()
+            .map(capitalise)
+            .collect::<Vec<String>>()
+            .join(" ");
+        if !genre.is_empty() && !genres.iter().any(|x| x.eq_ignore_ascii_case(&genre)) {
+            genres.push(genre);
+        }
+    }
+    genres
+}
+
+/// Upper cases the first letter of every hyphenated part of a word.
+fn capitalise(word: &str) -> String {
+    word.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::library_cmds::{load_library, load_positions};
+use crate::naming::{normalise_name, normalise_person};
+use crate::types::{ProgressEntry, ProgressExport, ProgressImportReport, Work};
+use crate::utils::{save_position, stored_path};
+
+const PROGRESS_FORMAT: &str = "audiobookplayer-progress";
+/// Bumped whenever the file layout changes, older versions must stay
+/// importable.
+const PROGRESS_VERSION: u32 = 1;
+
+/// Writes the resume position of every started work to a JSON file at `path`,
+/// returns how many were written.
+#[tauri::command]
+pub async fn export_progress(path: String) -> Result<usize, String> {
+    let works = load_library()
+        .await
+        .map_err(|_| "failed to load library".to_owned())?;
+    let positions = load_positions()
+        .await
+        .map_err(|_| "failed to load times".to_owned())?;
+
+    let entries = works
+        .iter()
+        .filter_map(|work| {
+            Some(ProgressEntry {
+                relative_path: relative_path(work),
+                title: work.title(),
+                author: work.author_names().into_iter().next().unwrap_or_default(),
+                fingerprints: work.fingerprints.clone(),
+                position: *positions.get(&work.id)?,
+            })
+        })
+        .collect::<Vec<ProgressEntry>>();
+    let count = entries.len();
+    let export = ProgressExport {
+        format: PROGRESS_FORMAT.to_owned(),
+        version: PROGRESS_VERSION,
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        entries,
+    };
+
+    let json = serde_json::to_string_pretty(&export)
+        .map_err(|err| format!("failed to serialise progress: {}", err))?;
+    fs::write(&path, json).map_err(|err| format!("failed to write {}: {}", path, err))?;
+    Ok(count)
+}
+
+/// Matches the entries of a file written by `export_progress` to the works
+/// of this library, by audio fingerprint, then folder relative to its
+/// library root, then title and author. A work keeps its own position when
+/// it is further along.
+#[tauri::command]
+pub async fn import_progress(path: String) -> Result<ProgressImportReport, String> {
+    let json =
+        fs::read_to_string(&path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let export: ProgressExport = serde_json::from_str(&json)
+        .map_err(|err| format!("{} is not a progress export: {}", path, err))?;
+    if export.format != PROGRESS_FORMAT {
+        return Err(format!("{} is not a progress export", path));
+    }
+    if export.version == 0 || export.version > PROGRESS_VERSION {
+        return Err(format!(
+            "progress version {} is not supported, up to version {} can be imported",
+            export.version, PROGRESS_VERSION
+        ));
+    }
+
+    let works = load_library()
+        .await
+        .map_err(|_| "failed to load library".to_owned())?;
+    let positions = load_positions()
+        .await
+        .map_err(|_| "failed to load times".to_owned())?;
+
+    // several entries can land on the same work, the furthest one wins
+    let mut furthest: HashMap<&str, f64> = HashMap::new();
+    let mut report = ProgressImportReport::default();
+    for entry in export.entries {
+        match match_work(&works, &entry) {
+            Some(work) => {
+                report.matched += 1;
+                let position = furthest.entry(work.id.as_str()).or_default();
+                *position = f64::max(*position, entry.position);
+            }
+            None => report.unmatched.push(entry),
+        }
+    }
+
+    for (work_id, position) in furthest {
+        if positions.get(work_id).map_or(false, |x| *x >= position) {
+            continue;
+        }
+        save_position(work_id, position).await?;
+    }
+    Ok(report)
+}
+
+fn match_work<'a>(works: &'a [Work], entry: &ProgressEntry) -> Option<&'a Work> {
+    let by_fingerprint = works.iter().find(|work| {
+        work.fingerprints
+            .iter()
+            .any(|x| entry.fingerprints.contains(x))
+    });
+    if by_fingerprint.is_some() {
+        return by_fingerprint;
+    }
+
+    if !entry.relative_path.is_empty() {
+        let by_path = works
+            .iter()
+            .find(|work| relative_path(work) == entry.relative_path);
+        if by_path.is_some() {
+            return by_path;
+        }
+    }
+
+    // a title and author shared by several works says nothing about which one
+    let entry_title = normalise_name(&entry.title);
+    let entry_author = normalise_person(&entry.author);
+    if entry_title.is_empty() {
+        return None;
+    }
+    let mut by_name = works.iter().filter(|work| {
+        normalise_name(&work.title()) == entry_title
+            && normalise_person(&work.author_names().into_iter().next().unwrap_or_default())
+                == entry_author
+    });
+    match (by_name.next(), by_name.next()) {
+        (Some(work), None) => Some(work),
+        _ => None,
+    }
+}
+
+/// Path of the work's folder below its library root, `/` separated so
+/// exports carry over between platforms.
+fn relative_path(work: &Work) -> String {
+    let path = stored_path(&work.path);
+    let relative = match path.strip_prefix(&work.root) {
+        Ok(relative) if !work.root.is_empty() => relative,
+        _ => Path::new(path.file_name().unwrap_or_default()),
+    };
+    relative
+        .iter()
+        .map(|x| x.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -1,301 +1,873 @@
-use lofty::TaggedFileExt;
-use log::{debug, error, info};
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::Write;
+use lofty::{Accessor, AudioFile, TaggedFileExt};
+use log::{debug, error, info, warn};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use surrealdb::sql::{thing, Value};
 use tauri::AppHandle;
+use tokio::sync::mpsc;
 use walkdir::WalkDir;
 
+use crate::covers::{cover_cache_dir, resolve_work_cover};
+use crate::duplicate_cmds::load_merged_paths;
+use crate::fingerprint::fingerprint_files;
+use crate::folder_template::{FolderTemplate, DEFAULT_FOLDER_TEMPLATES};
+use crate::library_cmds::clean_cover_cache;
+use crate::naming::{
+    natural_cmp, normalise_genres, normalise_name, parse_disc_number, parse_index_tag,
+    parse_series_index, parse_year,
+};
+use crate::scan_progress::{is_scan_cancelled, request_cancel, ScanGuard, ScanReporter};
 use crate::settings_cmds::load_settings;
-use crate::types::{MetadataTemplate, Work};
-use crate::utils::{create_author, create_work, AUDIO_FILE_EXTENSIONS, IMAGE_FILE_EXTENSIONS};
+use crate::sidecar::read_sidecars;
+use crate::track_cache::{load_track_metadata, summarise_audio};
+use crate::types::{
+    FolderTemplatePreview, LibraryRoot, LibraryStyle, MetadataField, MetadataTemplate, ReviewFlag,
+    ScanError, ScanFailure, ScanFailureKind, ScanPhase, ScanReport, ScanReportSummary, Settings,
+    TagSource, Work, WorkMetadata,
+};
+use crate::utils::{
+    backfill_work_roots, create_work, display_path, from_value, get_files_by_extension,
+    get_freeform_tag, has_extension, into_iter_objects, mark_missing_works, path_to_stored,
+    set_root_offline, split_people, stored_path, AUDIO_FILE_EXTENSIONS, IMAGE_FILE_EXTENSIONS,
+};
+use crate::DB;
+use crate::SES;
+
+/// How deep below the library root the folder scanner looks for works.
+const MAX_FOLDER_DEPTH: usize = 6;
+
+/// Number of folders shown by `preview_folder_template`.
+const TEMPLATE_PREVIEW_SAMPLES: usize = 25;
+
+/// Most threads reading tags at once during a metadata scan.
+const MAX_SCAN_WORKERS: usize = 8;
+
+/// Scans every enabled library root with the style set for it.
+#[tauri::command]
+pub async fn scan_library(app_handle: AppHandle, window: tauri::Window) -> Result<(), ScanError> {
+    scan_roots(app_handle, window, None).await
+}
+
+#[tauri::command]
+pub async fn scan_folder(app_handle: AppHandle, window: tauri::Window) -> Result<(), ScanError> {
+    scan_roots(app_handle, window, Some(LibraryStyle::Folder)).await
+}
 
 #[tauri::command]
-pub async fn scan_folder(app_handle: AppHandle, window: tauri::Window) {
+pub async fn scan_hybrid(app_handle: AppHandle, window: tauri::Window) -> Result<(), ScanError> {
+    scan_roots(app_handle, window, Some(LibraryStyle::Hybrid)).await
+}
+
+#[tauri::command]
+pub async fn scan_metadata(app_handle: AppHandle, window: tauri::Window) -> Result<(), ScanError> {
+    scan_roots(app_handle, window, Some(LibraryStyle::Metadata)).await
+}
+
+/// Scans the enabled library roots with `style`, or with the style of each
+/// root when none is given. The works of a root that is not mounted are
+/// marked offline and kept.
+async fn scan_roots(
+    app_handle: AppHandle,
+    window: tauri::Window,
+    style: Option<LibraryStyle>,
+) -> Result<(), ScanError> {
+    let _guard = ScanGuard::acquire()?;
+
     info!("loading settings");
-    let settings = load_settings(app_handle)
+    let settings = load_settings(app_handle.clone())
         .await
-        .expect("Failed to load settings");
+        .map_err(ScanError::Settings)?;
+    let roots: Vec<LibraryRoot> = settings.roots().into_iter().filter(|x| x.enabled).collect();
+    if roots.is_empty() {
+        return Err(ScanError::NoLibraryRoots);
+    }
 
     info!("library scanning");
-    let mut library: Vec<Work> = vec![];
+    let mut reporter = ScanReporter::new(window, style.clone());
+    let covers = cover_cache_dir(&app_handle);
 
-    window
-        .emit("scan_finding_files", 0)
-        .expect("event emit failed");
-
-    let authors = fs::read_dir(settings.library_location.clone());
-    for author in authors.unwrap() {
-        // authors
-        let au = author.unwrap();
-        let apath = au.path();
-
-        if au.file_type().unwrap().is_dir() {
-            let works = fs::read_dir(apath).unwrap();
-            let author_name = au.file_name().into_string().unwrap();
-
-            let author_id = create_author(author_name).await.unwrap();
-
-            for work in works {
-                let wu = work.unwrap();
-                let wpath = wu.path();
-
-                let work_name = wu.file_name().into_string().unwrap();
-                if wu.file_type().unwrap().is_dir() {
-                    let mut subworks = fs::read_dir(wpath.clone()).unwrap();
-
-                    let is_work_series = subworks.any(|x| x.unwrap().file_type().unwrap().is_dir());
-                    let files = fs::read_dir(wpath.clone())
-                        .unwrap()
-                        .map(|x| x.unwrap().path().as_os_str().to_str().unwrap().to_string())
-                        .collect();
-                    if !is_work_series {
-                        library.push(Work {
-                            author: author_id.clone(),
-                            series: None,
-                            files,
-                            name: work_name,
-                            path: wpath.clone().into_os_string().into_string().unwrap(),
-                            ..Default::default()
-                        });
-                    }
-
-                    for subwork in fs::read_dir(wpath).unwrap() {
-                        let swu = subwork.unwrap();
-                        let swpath = swu.path();
-
-                        if swu.file_type().unwrap().is_dir() {
-                            let subsubworks = fs::read_dir(swpath.clone()).unwrap();
-
-                            library.push(Work {
-                                author: author_id.clone(),
-                                series: Some(wu.file_name().into_string().unwrap()),
-                                files: subsubworks
-                                    .map(|x| {
-                                        x.unwrap().path().as_os_str().to_str().unwrap().to_string()
-                                    })
-                                    .collect(),
-                                name: swu.file_name().into_string().unwrap(),
-                                path: swpath.clone().into_os_string().into_string().unwrap(),
-                                ..Default::default()
-                            });
-                        }
-                    }
-                }
+    for root in roots {
+        if is_scan_cancelled() {
+            break;
+        }
+
+        if let Err(err) = backfill_work_roots(&root.path).await {
+            error!("{}", err);
+        }
+        let online = Path::new(&root.path).is_dir();
+        if let Err(err) = set_root_offline(&root.path, !online).await {
+            error!("{}", err);
+        }
+        if !online {
+            warn!("Library root {:?} is offline", root.path);
+            continue;
+        }
+
+        reporter.root(&root.path);
+        let library = match style.clone().unwrap_or_else(|| root.style.clone()) {
+            LibraryStyle::Folder => scan_folder_root(&settings, &root, &mut reporter).await,
+            LibraryStyle::Hybrid => scan_hybrid_root(&settings, &root, &mut reporter).await,
+            LibraryStyle::Metadata => scan_metadata_root(&settings, &root, &mut reporter).await,
+        };
+        save_library(library, &root.path, covers.as_deref(), &mut reporter).await;
+
+        if !is_scan_cancelled() {
+            if let Err(err) = mark_missing_works(&root.path).await {
+                error!("{}", err);
             }
         }
     }
 
-    for work in library {
-        if let Err(err) = create_work(work).await {
-            error!("Failed to add work: {}", err);
+    if !is_scan_cancelled() {
+        if let Err(err) = clean_cover_cache(app_handle).await {
+            error!("Failed to clean cover cache: {}", err);
         }
     }
-
-    window.emit("scan_complete", 0).expect("event emit failed");
+    reporter.finish().await;
 
     info!("library scanned and saved");
+    Ok(())
+}
+
+/// Makes one work of every folder holding audio files, named by the first
+/// matching folder template or, failing that, by the tags.
+async fn scan_folder_root(
+    settings: &Settings,
+    root: &LibraryRoot,
+    reporter: &mut ScanReporter,
+) -> Vec<Work> {
+    let templates = load_folder_templates(settings, root);
+    let fallback = settings.metadata_template.clone();
+    let root = PathBuf::from(&root.path);
+    let folders = find_root_folders(&root, reporter).await;
+
+    read_folders(folders, reporter, move |path, files| {
+        let relative = relative_folders(&root, &stored_path(&path));
+        let metadata = match templates
+            .iter()
+            .find_map(|template| template.match_folders(&relative))
+        {
+            Some(mut metadata) => {
+                if let Some(tags) = read_work_tags(&files, &fallback) {
+                    metadata.fill_descriptive(tags);
+                }
+                metadata
+            }
+            None => {
+                debug!("No folder template matched {:?}", path);
+                metadata_from_tags(&stored_path(&path), &files, &fallback)
+            }
+        };
+        folder_work(path, files, metadata, vec![])
+    })
+    .await
+}
+
+/// Groups audio files into works by their folder, like `scan_folder_root`,
+/// then names them from their tags with the folder layout as fallback. Works
+/// where the folder and the tags disagree are flagged for review.
+async fn scan_hybrid_root(
+    settings: &Settings,
+    root: &LibraryRoot,
+    reporter: &mut ScanReporter,
+) -> Vec<Work> {
+    let templates = load_folder_templates(settings, root);
+    let template = settings.metadata_template.clone();
+    let root = PathBuf::from(&root.path);
+    let folders = find_root_folders(&root, reporter).await;
+
+    read_folders(folders, reporter, move |path, files| {
+        let relative = relative_folders(&root, &stored_path(&path));
+        let from_folder = templates
+            .iter()
+            .find_map(|template| template.match_folders(&relative))
+            .unwrap_or_else(|| WorkMetadata {
+                title: folder_name(&stored_path(&path)),
+                ..Default::default()
+            });
+        let from_tags = read_work_tags(&files, &template);
+
+        let (metadata, review) = match from_tags {
+            Some(from_tags) => combine_folder_and_tags(from_folder, from_tags),
+            None => (from_folder, vec![]),
+        };
+        folder_work(path, files, metadata, review)
+    })
+    .await
+}
+
+/// Turns every folder into a work with `read` on a blocking thread, the
+/// async side only reports progress as the works come in.
+async fn read_folders<F>(
+    folders: Vec<(String, Vec<String>)>,
+    reporter: &mut ScanReporter,
+    read: F,
+) -> Vec<Work>
+where
+    F: Fn(String, Vec<String>) -> Work + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let reader = tokio::task::spawn_blocking(move || {
+        for (path, files) in folders {
+            if is_scan_cancelled() || sender.send(read(path, files)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut library: Vec<Work> = vec![];
+    while let Some(work) = receiver.recv().await {
+        reporter.advance(&work.path);
+        library.push(work);
+    }
+    if let Err(err) = reader.await {
+        error!("Folder reading failed: {}", err);
+    }
+    library
+}
+
+/// Finds the work folders of a root and starts the reading phase over them.
+async fn find_root_folders(root: &Path, reporter: &mut ScanReporter) -> Vec<(String, Vec<String>)> {
+    reporter.phase(ScanPhase::FindingFiles, 0);
+
+    let root = root.to_owned();
+    let (folders, failures) = tokio::task::spawn_blocking(move || {
+        let mut failures = vec![];
+        let folders = find_work_folders(&root, &mut failures);
+        (folders, failures)
+    })
+    .await
+    .unwrap_or_else(|err| {
+        error!("Finding work folders failed: {}", err);
+        (vec![], vec![])
+    });
+    failures.into_iter().for_each(|x| reporter.fail(x));
+    reporter.saw_files(folders.iter().map(|(_, files)| files.len()).sum());
+    reporter.phase(ScanPhase::ReadingFiles, folders.len());
+    folders
 }
 
 #[tauri::command]
-pub async fn scan_metadata(app_handle: tauri::AppHandle, window: tauri::Window) {
-    scan_metadata_with_template(MetadataTemplate::default(), app_handle, window).await;
+pub async fn load_scan_reports() -> Result<Vec<ScanReportSummary>, String> {
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute(
+            "SELECT id, started_at, duration, style, files_seen, works_added, \
+            (works_updated OR 0) AS works_updated, cancelled, array::len(failures) AS failed \
+            FROM scan_reports ORDER BY started_at DESC",
+            &SES,
+            None,
+            false,
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+
+    into_iter_objects(result)?
+        .map(|object| from_value(Value::Object(object?)))
+        .collect()
 }
 
-fn get_tag_with_fallback<'a>(
-    tag: &'a lofty::Tag,
-    keys: &'a Vec<lofty::ItemKey>,
-) -> Option<&'a str> {
-    for key in keys {
-        if let Some(value) = tag.get_string(key) {
-            return Some(value);
+/// Files that failed during the scan `report_id`, so broken tags can be fixed.
+#[tauri::command]
+pub async fn load_scan_problem_files(report_id: String) -> Result<Vec<ScanFailure>, String> {
+    let report = thing(&report_id).map_err(|_| "invalid scan report id".to_owned())?;
+    if report.tb != "scan_reports" {
+        return Err("invalid scan report id".into());
+    }
+    let data: BTreeMap<String, Value> = BTreeMap::from([("report".into(), report.into())]);
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute("SELECT * FROM $report", &SES, Some(data), false)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    match into_iter_objects(result)?.next() {
+        Some(object) => Ok(from_value::<ScanReport>(Value::Object(object?))?.failures),
+        None => Err("scan report not found".into()),
+    }
+}
+
+/// Stops the running scan, works already saved are kept.
+#[tauri::command]
+pub fn cancel_scan() -> bool {
+    request_cancel()
+}
+
+/// Fills the folder derived metadata with every value found in the tags and
+/// lists the fields where the two sources name different things.
+fn combine_folder_and_tags(
+    from_folder: WorkMetadata,
+    from_tags: WorkMetadata,
+) -> (WorkMetadata, Vec<ReviewFlag>) {
+    let mut review = vec![];
+    let mut compare = |field: MetadataField, folder: String, tags: String| {
+        if disagree(&folder, &tags) {
+            review.push(ReviewFlag {
+                field,
+                folder,
+                tags,
+            });
         }
+    };
+    compare(
+        MetadataField::Title,
+        from_folder.title.clone(),
+        from_tags.title.clone(),
+    );
+    compare(
+        MetadataField::Authors,
+        from_folder.authors.join(", "),
+        from_tags.authors.join(", "),
+    );
+    compare(
+        MetadataField::Series,
+        from_folder.series.clone().unwrap_or_default(),
+        from_tags.series.clone().unwrap_or_default(),
+    );
+
+    let mut metadata = from_folder;
+    metadata.merge_scanned(from_tags);
+    (metadata, review)
+}
+
+/// Two names disagree when both are set and neither contains the other once
+/// case, spacing and punctuation are ignored.
+fn disagree(a: &str, b: &str) -> bool {
+    let (a, b) = (normalise_name(a), normalise_name(b));
+    !a.is_empty() && !b.is_empty() && !a.contains(&b) && !b.contains(&a)
+}
+
+fn folder_work(
+    path: String,
+    files: Vec<String>,
+    metadata: WorkMetadata,
+    review: Vec<ReviewFlag>,
+) -> Work {
+    Work {
+        author: metadata.authors.join(", "),
+        series: metadata.series.clone(),
+        files,
+        name: metadata.title.clone(),
+        path,
+        metadata,
+        review,
+        ..Default::default()
     }
-    None
 }
 
-async fn scan_metadata_with_template(
-    template: MetadataTemplate,
-    app_handle: tauri::AppHandle,
-    window: tauri::Window,
+/// Saves the works found in a library root, resolving their covers into the
+/// cover cache `covers` along the way. Sidecar metadata files in a work's
+/// folder take precedence over its tags and folder names.
+async fn save_library(
+    mut library: Vec<Work>,
+    root: &str,
+    covers: Option<&Path>,
+    reporter: &mut ScanReporter,
 ) {
-    info!("loading settings");
-    let settings = load_settings(app_handle.clone())
-        .await
-        .expect("Failed to load settings");
+    if is_scan_cancelled() {
+        return;
+    }
+    let merged = load_merged_paths().await.unwrap_or_else(|err| {
+        error!("{}", err);
+        HashSet::new()
+    });
+    library.retain(|work| !merged.contains(&work.path));
 
-    info!("library scanning");
-    let mut library: HashMap<String, Work> = HashMap::new();
-    let mut authors: HashMap<String, String> = HashMap::new();
+    reporter.phase(ScanPhase::Saving, library.len());
+
+    for mut work in library {
+        if is_scan_cancelled() {
+            break;
+        }
+
+        let audio_files =
+            get_files_by_extension(work.files.clone(), AUDIO_FILE_EXTENSIONS.to_vec());
+        let tracks = load_track_metadata(&audio_files).await;
+        if work.duration.is_zero() {
+            work.duration = tracks.tracks.iter().map(|x| x.duration).sum();
+        }
+        work.audio = summarise_audio(&tracks.tracks);
+        work.root = root.to_owned();
+        let path = work.path.clone();
+        let covers = covers.map(Path::to_path_buf);
+        let read = tokio::task::spawn_blocking(move || {
+            read_work_folder(&mut work, &audio_files, covers.as_deref());
+            work
+        })
+        .await
+        .map_err(|err| format!("failed to read work folder: {}", err));
+        let saved = match read {
+            Ok(work) => create_work(work).await,
+            Err(err) => Err(err),
+        };
+        match saved {
+            Ok(created) => reporter.saved(&path, created),
+            Err(err) => {
+                error!("Failed to add work: {}", err);
+                reporter.fail(ScanFailure {
+                    path,
+                    kind: ScanFailureKind::Save,
+                    message: err,
+                });
+            }
+        }
+    }
+}
 
-    window
-        .emit("scan_finding_files", 0)
-        .expect("event emit failed");
+/// Reads the sidecar metadata, cover and audio fingerprints of a work from
+/// its folder.
+fn read_work_folder(work: &mut Work, audio_files: &[String], covers: Option<&Path>) {
+    if let Some(sidecar) = read_sidecars(&stored_path(&work.path)) {
+        let settled = sidecar.changed_fields(&WorkMetadata::default());
+        work.review.retain(|x| !settled.contains(&x.field));
+        work.metadata.merge_scanned(sidecar);
+    }
+    if let Some(covers) = covers {
+        work.cover = resolve_work_cover(covers, &work.files);
+    }
+    work.fingerprints = fingerprint_files(audio_files);
+}
 
-    let files = WalkDir::new(settings.library_location.clone())
-        .max_depth(4)
+/// Shows how the first folders of a library root would be read with a
+/// template, `root` defaults to the first configured root.
+#[tauri::command]
+pub async fn preview_folder_template(
+    app_handle: AppHandle,
+    template: String,
+    root: Option<String>,
+) -> Result<Vec<FolderTemplatePreview>, String> {
+    let settings = load_settings(app_handle).await?;
+    let template = FolderTemplate::parse(&template)?;
+    let Some(root) = root.or_else(|| settings.roots().into_iter().next().map(|x| x.path)) else {
+        return Err("no library root configured".into());
+    };
+    let root = PathBuf::from(root);
+
+    Ok(find_work_folders(&root, &mut vec![])
         .into_iter()
-        .filter_map(|e| match e {
-            Ok(dir) => {
-                if dir.path().extension().is_some()
-                    && AUDIO_FILE_EXTENSIONS
-                        .contains(&dir.path().extension().unwrap().to_str().unwrap())
-                {
-                    Some(dir)
-                } else {
-                    None
-                }
+        .take(TEMPLATE_PREVIEW_SAMPLES)
+        .map(|(path, _)| {
+            let metadata = template.match_folders(&relative_folders(&root, &stored_path(&path)));
+            FolderTemplatePreview {
+                path: display_path(&path),
+                matched: metadata.is_some(),
+                metadata: metadata.unwrap_or_default(),
             }
-            Err(..) => None,
         })
-        .collect::<Vec<_>>();
+        .collect())
+}
 
-    window
-        .emit("scan_metadata_files_found", files.len())
-        .expect("event emit failed");
+fn load_folder_templates(settings: &Settings, root: &LibraryRoot) -> Vec<FolderTemplate> {
+    let templates = if !root.folder_templates.is_empty() {
+        root.folder_templates.clone()
+    } else if !settings.folder_templates.is_empty() {
+        settings.folder_templates.clone()
+    } else {
+        DEFAULT_FOLDER_TEMPLATES
+            .iter()
+            .map(|x| x.to_string())
+            .collect()
+    };
+
+    templates
+        .iter()
+        .filter_map(|template| match FolderTemplate::parse(template) {
+            Ok(parsed) => Some(parsed),
+            Err(err) => {
+                error!("Skipping folder template {:?}: {}", template, err);
+                None
+            }
+        })
+        .collect()
+}
 
-    for entry in files {
-        let Ok(meta) = lofty::read_from_path(entry.path()) else {
-            debug!("Failed Read {:?}", entry);
-            window.emit("scan_metadata_file_failed_read", entry.path().to_str().unwrap().to_string()).expect("event emit failed");
-            continue;
-        };
+fn relative_folders(root: &Path, path: &Path) -> Vec<String> {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy().to_string())
+        .collect()
+}
 
-        let tag = if meta.primary_tag().is_some() {
-            meta.primary_tag().unwrap()
-        } else if meta.first_tag().is_some() {
-            meta.first_tag().unwrap()
-        } else {
-            debug!("Failed Read Tag {:?}", entry);
-            window
-                .emit(
-                    "scan_metadata_file_failed_tag_read",
-                    entry.path().to_str().unwrap().to_string(),
-                )
-                .expect("event emit failed");
-            continue;
+/// Finds every folder holding audio files, directly or through disc folders,
+/// together with the files that make up its work. Entries that cannot be read
+/// are skipped and added to `failures`.
+fn find_work_folders(root: &Path, failures: &mut Vec<ScanFailure>) -> Vec<(String, Vec<String>)> {
+    let mut works = vec![];
+    let mut disc_folders: HashSet<PathBuf> = HashSet::new();
+
+    let mut walker = WalkDir::new(root)
+        .min_depth(1)
+        .max_depth(MAX_FOLDER_DEPTH)
+        .sort_by(|a, b| {
+            natural_cmp(
+                &a.file_name().to_string_lossy(),
+                &b.file_name().to_string_lossy(),
+            )
+        })
+        .into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                failures.push(walk_failure(&err));
+                continue;
+            }
         };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        if disc_folders.contains(entry.path()) {
+            walker.skip_current_dir();
+            continue;
+        }
 
-        let Some(track_author) = get_tag_with_fallback(tag, &template.author) else {
-            debug!("Failed Read Author {:?}", entry);
-            window.emit("scan_metadata_file_failed_author_read", entry.path().to_str().unwrap().to_string()).expect("event emit failed");
+        let discs = find_disc_folders(entry.path(), failures);
+        let files = collect_work_files(entry.path(), &discs, failures);
+        disc_folders.extend(discs);
+
+        if get_files_by_extension(files.clone(), AUDIO_FILE_EXTENSIONS.to_vec()).is_empty() {
             continue;
-        };
+        }
+        works.push((path_to_stored(entry.path()), files));
+    }
+    works
+}
 
-        let author_id = if authors.contains_key(track_author) {
-            authors.get(track_author).unwrap().to_owned()
-        } else {
-            let key = create_author(track_author.clone().to_owned())
-                .await
-                .unwrap();
-            authors.insert(track_author.clone().to_owned(), key.clone());
-            key
-        };
+fn walk_failure(err: &walkdir::Error) -> ScanFailure {
+    error!("Failed to read directory entry: {}", err);
+    ScanFailure {
+        path: err
+            .path()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        kind: ScanFailureKind::DirectoryRead,
+        message: err.to_string(),
+    }
+}
 
-        let Some(album_title) = get_tag_with_fallback(tag, &template.title) else {
-                debug!("Failed Read Album {:?}", entry);
-                window.emit("scan_metadata_file_failed_album_read", entry.path().to_str().unwrap().to_string()).expect("event emit failed");
-                continue;
-            };
-
-        let library_key = track_author.to_owned() + album_title;
-
-        let mut work = if library.contains_key(&library_key) {
-            library.remove(&library_key).unwrap()
-        } else {
-            let images = WalkDir::new(entry.path().parent().unwrap())
-                .into_iter()
-                .filter_map(|e| match e {
-                    Ok(dir) => {
-                        if dir.path().extension().is_some()
-                            && IMAGE_FILE_EXTENSIONS
-                                .contains(&dir.path().extension().unwrap().to_str().unwrap())
-                        {
-                            Some(dir.path().to_str().unwrap().to_string())
-                        } else {
-                            None
-                        }
-                    }
-                    Err(..) => None,
-                })
-                .collect::<Vec<_>>();
-            Work {
-                author: author_id,
-                series: None,
-                files: images.clone(),
-                image_files: images,
-                name: album_title.to_owned(),
-                path: entry.path().parent().unwrap().to_str().unwrap().to_string(),
-                ..Default::default()
+fn folder_name(path: &Path) -> String {
+    path.file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Reads the title and author from the tags of a work's first audio file, for
+/// folders that match no template. The folder name is used as a last resort.
+fn metadata_from_tags(path: &Path, files: &[String], template: &MetadataTemplate) -> WorkMetadata {
+    let mut metadata = read_work_tags(files, template).unwrap_or_default();
+    if metadata.title.is_empty() {
+        metadata.title = folder_name(path);
+    }
+    metadata
+}
+
+/// Reads the tags of the first audio file of a work.
+fn read_work_tags(files: &[String], template: &MetadataTemplate) -> Option<WorkMetadata> {
+    let mut audio_files = get_files_by_extension(files.to_vec(), AUDIO_FILE_EXTENSIONS.to_vec());
+    audio_files.sort_by(|a, b| natural_cmp(a, b));
+
+    let tagged = lofty::read_from_path(stored_path(audio_files.first()?)).ok()?;
+    let tag = tagged.primary_tag().or_else(|| tagged.first_tag())?;
+    Some(tag_metadata(tag, template))
+}
+
+/// Reads every field of the metadata template from a tag, missing fields are
+/// left empty.
+fn tag_metadata(tag: &lofty::Tag, template: &MetadataTemplate) -> WorkMetadata {
+    let title = get_tag_with_fallback(tag, &template.title)
+        .map(str::to_owned)
+        .unwrap_or_default();
+
+    let series = get_tag_with_fallback(tag, &template.series).map(str::to_owned);
+
+    WorkMetadata {
+        // a number in the title of a standalone book is not an index
+        series_index: get_tag_with_fallback(tag, &template.series_index)
+            .and_then(parse_index_tag)
+            .or_else(|| series.as_ref().and_then(|_| parse_series_index(&title))),
+        series,
+        title,
+        authors: get_tag_with_fallback(tag, &template.author)
+            .map(split_people)
+            .unwrap_or_default(),
+        narrators: get_tag_with_fallback(tag, &template.narrator)
+            .map(split_people)
+            .unwrap_or_default(),
+        published_year: get_tag_with_fallback(tag, &template.year).and_then(parse_year),
+        description: get_tag_with_fallback(tag, &template.description).map(str::to_owned),
+        genres: get_tag_with_fallback(tag, &template.genre)
+            .map(normalise_genres)
+            .unwrap_or_default(),
+        publisher: get_tag_with_fallback(tag, &template.publisher).map(str::to_owned),
+        copyright: get_tag_with_fallback(tag, &template.copyright).map(str::to_owned),
+        language: get_tag_with_fallback(tag, &template.language).map(str::to_owned),
+        ..Default::default()
+    }
+}
+
+/// Lists the subfolders of a work that hold one disc of it.
+fn find_disc_folders(path: &Path, failures: &mut Vec<ScanFailure>) -> Vec<PathBuf> {
+    WalkDir::new(path)
+        .min_depth(1)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                failures.push(walk_failure(&err));
+                None
+            }
+        })
+        .filter(|x| x.file_type().is_dir() && is_disc_folder(x.path()))
+        .map(|x| x.into_path())
+        .collect()
+}
+
+/// A folder is one disc of a rip when its name says so ("CD1", "Disc 2",
+/// "Part 03"), or when all of its audio files are tagged with the same disc
+/// number of a multi-disc set.
+fn is_disc_folder(path: &Path) -> bool {
+    if parse_disc_number(&folder_name(path)).is_some() {
+        return true;
+    }
+
+    let discs = WalkDir::new(path)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| has_extension(entry.path(), &AUDIO_FILE_EXTENSIONS))
+        .map(|entry| {
+            let tagged = lofty::Probe::open(entry.path())
+                .ok()?
+                .options(lofty::ParseOptions::new().read_properties(false))
+                .read()
+                .ok()?;
+            let tag = tagged.primary_tag().or_else(|| tagged.first_tag())?;
+            Some((tag.disk()?, tag.disk_total().unwrap_or_default()))
+        })
+        .collect::<Vec<_>>();
+
+    match discs.first() {
+        Some(Some((disc, total))) => {
+            (*disc > 1 || *total > 1)
+                && discs.iter().all(|x| matches!(x, Some((d, _)) if d == disc))
+        }
+        _ => false,
+    }
+}
+
+/// Lists the files of a work folder together with the files of its disc folders.
+fn collect_work_files(
+    path: &Path,
+    discs: &[PathBuf],
+    failures: &mut Vec<ScanFailure>,
+) -> Vec<String> {
+    let folder = WalkDir::new(path).min_depth(1).max_depth(1);
+    let mut files = vec![];
+    for entry in folder.into_iter().chain(
+        discs
+            .iter()
+            .flat_map(|disc| WalkDir::new(disc).min_depth(1)),
+    ) {
+        match entry {
+            Ok(entry) if entry.file_type().is_dir() => {}
+            Ok(entry) => files.push(path_to_stored(entry.path())),
+            Err(err) => failures.push(walk_failure(&err)),
+        }
+    }
+    files
+}
+
+fn get_tag_with_fallback<'a>(tag: &'a lofty::Tag, sources: &[TagSource]) -> Option<&'a str> {
+    for source in sources {
+        let value = match (source, source.item_key()) {
+            (_, Some(key)) => tag.get_string(&key),
+            (TagSource::Key(name), None) | (TagSource::Custom(name), None) => {
+                get_freeform_tag(tag, name)
             }
         };
+        if let Some(value) = value.filter(|x| !x.trim().is_empty()) {
+            return Some(value);
+        }
+    }
+    None
+}
 
-        if work.image_files.is_empty() {
-            let mut cover_pics = tag
-                .pictures()
-                .iter()
-                .filter(|pic| pic.pic_type() == lofty::PictureType::CoverFront);
-            if cover_pics.clone().count() > 0 {
-                let cover_pic = cover_pics.next().unwrap();
-                let extension = match cover_pic.mime_type().as_str() {
-                    "image/jpeg" => "jpg",
-                    "image/png" => "png",
-                    "image/gif" => "gif",
-                    "image/bmp" => "bmp",
-                    e => {
-                        error!("missing file type support: {}", e);
-                        "jpg"
-                    }
-                };
-                let cover_path = app_handle
-                    .path_resolver()
-                    .app_cache_dir()
-                    .unwrap()
-                    .clone()
-                    .join("cache_covers")
-                    .join(format!(
-                        "{}.{}",
-                        regex::Regex::new(r"[^A-Za-z0-9 ]")
-                            .unwrap()
-                            .replace_all(album_title, ""),
-                        extension
-                    ));
-
-                if cover_path.exists() {
-                    work.files.push(cover_path.to_str().unwrap().into());
-                } else {
-                    if !cover_path.parent().unwrap().exists() {
-                        fs::create_dir_all(cover_path.parent().unwrap()).unwrap();
-                    }
-                    match File::create(&cover_path) {
-                        Ok(mut cover_file) => {
-                            cover_file.write_all(cover_pic.data()).unwrap();
-                            work.files.push(cover_path.to_str().unwrap().into());
-                        }
-                        Err(err) => {
-                            error!("Failed to create cover file: {:?}: {:?}", cover_path, err)
-                        }
-                    }
+/// What a tag reading worker learned from one audio file.
+struct ScannedFile {
+    author: String,
+    title: String,
+    metadata: WorkMetadata,
+    duration: Duration,
+}
+
+type ScanResult = (String, Result<ScannedFile, (ScanFailureKind, String)>);
+
+/// Groups the audio files of a root into works by the author and title in
+/// their tags, reading the tags on a pool of worker threads.
+async fn scan_metadata_root(
+    settings: &Settings,
+    root: &LibraryRoot,
+    reporter: &mut ScanReporter,
+) -> Vec<Work> {
+    let mut library: HashMap<String, Work> = HashMap::new();
+    reporter.phase(ScanPhase::FindingFiles, 0);
+
+    let walk_root = root.path.clone();
+    let (files, failures) = tokio::task::spawn_blocking(move || {
+        let mut failures = vec![];
+        let mut files = vec![];
+        for entry in WalkDir::new(walk_root).max_depth(4) {
+            match entry {
+                Ok(entry) if has_extension(entry.path(), &AUDIO_FILE_EXTENSIONS) => {
+                    files.push(path_to_stored(entry.path()))
                 }
+                Ok(_) => {}
+                Err(err) => failures.push(walk_failure(&err)),
             }
         }
+        (files, failures)
+    })
+    .await
+    .unwrap_or_else(|err| {
+        error!("Finding audio files failed: {}", err);
+        (vec![], vec![])
+    });
+    failures.into_iter().for_each(|x| reporter.fail(x));
+
+    reporter.saw_files(files.len());
+    reporter.phase(ScanPhase::ReadingFiles, files.len());
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let template = settings.metadata_template.clone();
+    let reader =
+        tokio::task::spawn_blocking(move || read_files_in_parallel(files, template, sender));
+
+    while let Some((path, result)) = receiver.recv().await {
+        let scanned = match result {
+            Ok(scanned) => scanned,
+            Err((kind, message)) => {
+                reporter.fail(ScanFailure {
+                    path: display_path(&path),
+                    kind,
+                    message,
+                });
+                continue;
+            }
+        };
 
-        work.files.push(entry.path().to_str().unwrap().to_string());
+        let library_key = scanned.author.clone() + &scanned.title;
+
+        let work = library.entry(library_key).or_insert_with(|| {
+            let file = stored_path(&path);
+            Work {
+                author: scanned.author.clone(),
+                series: None,
+                name: scanned.title.clone(),
+                path: path_to_stored(file.parent().unwrap_or(Path::new(""))),
+                metadata: scanned.metadata,
+                ..Default::default()
+            }
+        });
 
-        library.insert(library_key.clone(), work);
+        work.files.push(path.clone());
+        work.duration += scanned.duration;
 
-        window
-            .emit("scan_metadata_file_complete", 0)
-            .expect("event emit failed");
+        reporter.advance(&path);
     }
 
-    for work in library.values() {
-        if let Err(err) = create_work(work.clone()).await {
-            error!("Failed to add work: {}", err);
-        }
+    if let Err(err) = reader.await {
+        error!("Tag reading failed: {}", err);
     }
 
-    window.emit("scan_complete", 0).expect("event emit failed");
+    // images of a folder are listed ahead of its audio files
+    let library = library.into_values().collect::<Vec<Work>>();
+    tokio::task::spawn_blocking(move || {
+        library
+            .into_iter()
+            .map(|mut work| {
+                let images = WalkDir::new(stored_path(&work.path))
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter(|x| has_extension(x.path(), &IMAGE_FILE_EXTENSIONS))
+                    .map(|x| path_to_stored(x.path()))
+                    .collect::<Vec<_>>();
+                work.files.splice(0..0, images.iter().cloned());
+                work.image_files = images;
+                work
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_else(|err| {
+        error!("Finding cover images failed: {}", err);
+        vec![]
+    })
+}
 
-    info!("library scanned and saved");
+/// Reads the tags of `files` on a bounded pool of threads, sending each
+/// result as soon as it is ready. Stops early when the scan is cancelled.
+fn read_files_in_parallel(
+    files: Vec<String>,
+    template: MetadataTemplate,
+    sender: mpsc::UnboundedSender<ScanResult>,
+) {
+    let workers = thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+        .min(MAX_SCAN_WORKERS);
+    let queue = Mutex::new(files.into_iter());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                if is_scan_cancelled() {
+                    break;
+                }
+                let Some(path) = queue.lock().ok().and_then(|mut x| x.next()) else {
+                    break;
+                };
+                let result = read_scanned_file(&stored_path(&path), &template);
+                if sender.send((path, result)).is_err() {
+                    break;
+                }
+            });
+        }
+    });
+}
+
+fn read_scanned_file(
+    path: &Path,
+    template: &MetadataTemplate,
+) -> Result<ScannedFile, (ScanFailureKind, String)> {
+    let meta =
+        lofty::read_from_path(path).map_err(|err| (ScanFailureKind::FileRead, err.to_string()))?;
+    let Some(tag) = meta.primary_tag().or_else(|| meta.first_tag()) else {
+        return Err((ScanFailureKind::TagRead, "file has no tags".into()));
+    };
+    let Some(author) = get_tag_with_fallback(tag, &template.author) else {
+        return Err((
+            ScanFailureKind::AuthorRead,
+            format!("none of the author tags {:?} are set", template.author),
+        ));
+    };
+    let Some(title) = get_tag_with_fallback(tag, &template.title) else {
+        return Err((
+            ScanFailureKind::AlbumRead,
+            format!("none of the title tags {:?} are set", template.title),
+        ));
+    };
+
+    Ok(ScannedFile {
+        author: author.to_owned(),
+        title: title.to_owned(),
+        metadata: tag_metadata(tag, template),
+        duration: meta.properties().duration(),
+    })
 }
@@ -0,0 +1,183 @@
+use log::{error, warn};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::types::{LibraryStyle, ScanError, ScanFailure, ScanPhase, ScanProgress, ScanReport};
+use crate::utils::to_value;
+use crate::DB;
+use crate::SES;
+
+const SCAN_PROGRESS_EVENT: &str = "scan_progress";
+
+static SCAN_RUNNING: AtomicBool = AtomicBool::new(false);
+static SCAN_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Held for the length of a scan, only one scan can run at a time.
+pub struct ScanGuard;
+
+impl ScanGuard {
+    pub fn acquire() -> Result<Self, ScanError> {
+        if SCAN_RUNNING
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(ScanError::AlreadyRunning);
+        }
+        SCAN_CANCELLED.store(false, Ordering::SeqCst);
+        Ok(Self)
+    }
+}
+
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        SCAN_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Asks the running scan to stop, returns false when no scan is running.
+pub fn request_cancel() -> bool {
+    if !SCAN_RUNNING.load(Ordering::SeqCst) {
+        return false;
+    }
+    SCAN_CANCELLED.store(true, Ordering::SeqCst);
+    true
+}
+
+pub fn is_scan_cancelled() -> bool {
+    SCAN_CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Tracks the progress of a scan, emits it as `scan_progress` events and
+/// saves a [`ScanReport`] once the scan finishes.
+pub struct ScanReporter {
+    window: tauri::Window,
+    progress: ScanProgress,
+    phase_started: Instant,
+    scan_started: Instant,
+    report: ScanReport,
+}
+
+impl ScanReporter {
+    pub fn new(window: tauri::Window, style: Option<LibraryStyle>) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        let reporter = Self {
+            window,
+            progress: ScanProgress::default(),
+            phase_started: Instant::now(),
+            scan_started: Instant::now(),
+            report: ScanReport {
+                id: String::new(),
+                started_at,
+                duration: Duration::ZERO,
+                style,
+                files_seen: 0,
+                works_added: 0,
+                works_updated: 0,
+                cancelled: false,
+                failures: vec![],
+            },
+        };
+        reporter.emit();
+        reporter
+    }
+
+    /// Starts a phase of `total` items, the completed count and ETA restart.
+    pub fn phase(&mut self, phase: ScanPhase, total: usize) {
+        self.progress.phase = phase;
+        self.progress.total = total;
+        self.progress.completed = 0;
+        self.progress.current_path = None;
+        self.progress.eta = None;
+        self.phase_started = Instant::now();
+        self.emit();
+    }
+
+    /// Moves on to the next library root.
+    pub fn root(&mut self, root: &str) {
+        self.progress.root = Some(root.to_owned());
+    }
+
+    /// Counts audio and image files found under the library root.
+    pub fn saw_files(&mut self, count: usize) {
+        self.report.files_seen += count;
+    }
+
+    pub fn advance(&mut self, path: &str) {
+        self.step(path);
+        self.emit();
+    }
+
+    /// Advances past a saved work, `created` when it is new to the library.
+    pub fn saved(&mut self, path: &str, created: bool) {
+        if created {
+            self.report.works_added += 1;
+        } else {
+            self.report.works_updated += 1;
+        }
+        self.advance(path);
+    }
+
+    pub fn fail(&mut self, failure: ScanFailure) {
+        warn!("Scan failed {:?}: {}", failure.kind, failure.path);
+        self.step(&failure.path.clone());
+        self.progress.failed += 1;
+        self.report.failures.push(failure.clone());
+        self.progress.failure = Some(failure);
+        self.emit();
+        self.progress.failure = None;
+    }
+
+    /// Emits the final `Complete` or `Cancelled` phase and saves the report.
+    pub async fn finish(mut self) {
+        let cancelled = is_scan_cancelled();
+        self.progress.phase = if cancelled {
+            ScanPhase::Cancelled
+        } else {
+            ScanPhase::Complete
+        };
+        self.progress.root = None;
+        self.progress.current_path = None;
+        self.progress.eta = None;
+
+        self.report.cancelled = cancelled;
+        self.report.duration = self.scan_started.elapsed();
+        if let Err(err) = save_report(&self.report).await {
+            error!("Failed to save scan report: {}", err);
+        }
+        self.emit();
+    }
+
+    fn step(&mut self, path: &str) {
+        self.progress.completed += 1;
+        self.progress.current_path = Some(path.to_owned());
+
+        let remaining = self.progress.total.saturating_sub(self.progress.completed);
+        let per_item = self.phase_started.elapsed().as_secs_f64() / self.progress.completed as f64;
+        self.progress.eta = Some(Duration::from_secs_f64(per_item * remaining as f64));
+    }
+
+    fn emit(&self) {
+        if let Err(err) = self.window.emit(SCAN_PROGRESS_EVENT, &self.progress) {
+            error!("Failed to emit scan progress: {}", err);
+        }
+    }
+}
+
+async fn save_report(report: &ScanReport) -> Result<(), String> {
+    let vars = BTreeMap::from([("report".to_owned(), to_value(report)?)]);
+    DB.get()
+        .expect("DB does not exist")
+        .execute(
+            "CREATE scan_reports CONTENT $report",
+            &SES,
+            Some(vars),
+            false,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
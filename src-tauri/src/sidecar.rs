@@ -0,0 +1,530 @@
+use log::warn;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value as JsonValue;
+use std::fs;
+use std::path::Path;
+
+use crate::naming::{normalise_genres, parse_index_tag, parse_year};
+use crate::types::WorkMetadata;
+use crate::utils::split_people;
+
+/// Audiobookshelf's metadata file.
+pub const ABS_METADATA_FILE: &str = "metadata.json";
+/// Calibre and Audiobookshelf's OPF package.
+pub const OPF_FILE: &str = "metadata.opf";
+const DESCRIPTION_FILE: &str = "desc.txt";
+const NARRATOR_FILE: &str = "reader.txt";
+const NFO_EXTENSION: &str = "nfo";
+
+/// Readers of every sidecar, from the lowest precedence to the highest.
+const SIDECAR_READERS: [fn(&Path) -> Option<WorkMetadata>; 5] = [
+    read_narrator_file,
+    read_description_file,
+    read_nfo_file,
+    read_opf_file,
+    read_abs_metadata_file,
+];
+
+/// `<dc:title>Title</dc:title>`, attributes in group 2 and text in group 3
+static OPF_ELEMENT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<dc:(\w+)([^>]*)>(.*?)</dc:\w+>").unwrap());
+/// `<meta name="calibre:series" content="Series"/>`
+static OPF_META: Lazy<Regex> = Lazy::new(|| Regex::new(r"<meta\s[^>]*>").unwrap());
+static XML_ATTRIBUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"([\w:-]+)\s*=\s*"([^"]*)""#).unwrap());
+static XML_ENTITY: Lazy<Regex> = Lazy::new(|| Regex::new(r"&(#x?[0-9a-fA-F]+|\w+);").unwrap());
+static XML_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]+>").unwrap());
+/// "Title: The Book", "Narrated By.....: Someone"
+static NFO_FIELD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*([A-Za-z][A-Za-z ]*?)\s*\.*\s*:\s*(.*?)\s*$").unwrap());
+/// A "Book Description" heading on a line of its own, as Libation writes it
+static NFO_DESCRIPTION_HEADING: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^\s*(?:book\s+)?(?:description|summary|synopsis)\s*:?\s*$").unwrap()
+});
+/// "Series Name #2", "Series Name, Book 2"
+static SERIES_WITH_INDEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(.*?)(?:\s*#|,\s*book\s+)(\d+(?:\.\d+)?)\s*$").unwrap());
+
+/// Reads the metadata files other tools leave in a work's folder. Values from
+/// `metadata.json` win over `metadata.opf`, which wins over `.nfo` files,
+/// which win over `desc.txt` and `reader.txt`. Scanners merge the result over
+/// what they read from tags and folder names.
+pub fn read_sidecars(folder: &Path) -> Option<WorkMetadata> {
+    SIDECAR_READERS
+        .iter()
+        .filter_map(|read| read(folder))
+        .reduce(|mut metadata, sidecar| {
+            metadata.merge_scanned(sidecar);
+            metadata
+        })
+}
+
+fn read_sidecar(path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return None;
+    }
+    match fs::read(path) {
+        Ok(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        Err(err) => {
+            warn!("Failed to read {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+fn read_narrator_file(folder: &Path) -> Option<WorkMetadata> {
+    let narrators = split_people(read_sidecar(&folder.join(NARRATOR_FILE))?.trim());
+    Some(WorkMetadata {
+        narrators,
+        ..Default::default()
+    })
+}
+
+fn read_description_file(folder: &Path) -> Option<WorkMetadata> {
+    let description = read_sidecar(&folder.join(DESCRIPTION_FILE))?;
+    Some(WorkMetadata {
+        description: non_empty(description.trim()),
+        ..Default::default()
+    })
+}
+
+fn read_nfo_file(folder: &Path) -> Option<WorkMetadata> {
+    let path = fs::read_dir(folder)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|x| x.path())
+        .find(|x| {
+            x.extension()
+                .and_then(|x| x.to_str())
+                .map_or(false, |x| x.eq_ignore_ascii_case(NFO_EXTENSION))
+        })?;
+    Some(parse_nfo(&read_sidecar(&path)?))
+}
+
+fn read_opf_file(folder: &Path) -> Option<WorkMetadata> {
+    Some(parse_opf(&read_sidecar(&folder.join(OPF_FILE))?))
+}
+
+fn read_abs_metadata_file(folder: &Path) -> Option<WorkMetadata> {
+    let path = folder.join(ABS_METADATA_FILE);
+    let metadata = parse_abs_metadata(&read_sidecar(&path)?);
+    if metadata.is_none() {
+        warn!("{:?} is not an Audiobookshelf metadata file", path);
+    }
+    metadata
+}
+
+/// Reads the "Key: Value" lines of a release `.nfo`. Everything after a
+/// description field or heading is taken as the description.
+fn parse_nfo(text: &str) -> WorkMetadata {
+    let mut metadata = WorkMetadata::default();
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        if NFO_DESCRIPTION_HEADING.is_match(line) {
+            let rest = lines
+                .by_ref()
+                .skip_while(|x| x.trim().chars().all(|c| c == '=' || c == '-'))
+                .collect::<Vec<&str>>()
+                .join("\n");
+            metadata.description = non_empty(&rest);
+            break;
+        }
+        let Some(captures) = NFO_FIELD.captures(line) else {
+            continue;
+        };
+        let key = captures[1].to_lowercase();
+        let value = captures[2].to_owned();
+
+        match key.as_str() {
+            "title" | "book title" => metadata.title = value,
+            "subtitle" => metadata.subtitle = non_empty(&value),
+            "author" | "authors" | "written by" => metadata.authors = split_people(&value),
+            "narrator" | "narrators" | "read by" | "narrated by" => {
+                metadata.narrators = split_people(&value)
+            }
+            "series" | "series name" => set_series(&mut metadata, &value),
+            "series number" | "series part" | "series sequence" | "book number" => {
+                metadata.series_index = parse_index_tag(&value)
+            }
+            "genre" | "genres" => metadata.genres = normalise_genres(&value),
+            "publisher" => metadata.publisher = non_empty(&value),
+            "year" | "release date" | "release year" | "published" => {
+                metadata.published_year = parse_year(&value)
+            }
+            "copyright" => {
+                metadata.copyright = non_empty(&value);
+                if metadata.published_year.is_none() {
+                    metadata.published_year = parse_year(&value);
+                }
+            }
+            "language" => metadata.language = non_empty(&value),
+            "isbn" => metadata.isbn = non_empty(&value),
+            "asin" => metadata.asin = non_empty(&value),
+            "description" | "book description" | "summary" | "synopsis" => {
+                let rest = lines.by_ref().collect::<Vec<&str>>().join("\n");
+                metadata.description = non_empty(format!("{}\n{}", value, rest).trim());
+            }
+            _ => {}
+        }
+    }
+    metadata
+}
+
+/// Reads the Dublin Core elements and Calibre series meta tags of an OPF
+/// package.
+fn parse_opf(text: &str) -> WorkMetadata {
+    let mut metadata = WorkMetadata::default();
+
+    for captures in OPF_ELEMENT.captures_iter(text) {
+        let attributes = xml_attributes(&captures[2]);
+        let value = xml_text(&captures[3]);
+        if value.is_empty() {
+            continue;
+        }
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key.rsplit(':').next() == Some(name))
+                .map(|(_, value)| value.to_lowercase())
+        };
+
+        match &captures[1] {
+            "title" if metadata.title.is_empty() => metadata.title = value,
+            "creator" => match attribute("role").as_deref() {
+                Some("nrt") => metadata.narrators.extend(split_people(&value)),
+                Some("aut") | None => metadata.authors.extend(split_people(&value)),
+                _ => {}
+            },
+            "contributor" if attribute("role").as_deref() == Some("nrt") => {
+                metadata.narrators.extend(split_people(&value))
+            }
+            "description" => metadata.description = Some(value),
+            "publisher" => metadata.publisher = Some(value),
+            "date" => metadata.published_year = parse_year(&value),
+            "language" => metadata.language = Some(value),
+            "rights" => metadata.copyright = Some(value),
+            "subject" => {
+                for genre in normalise_genres(&value) {
+                    if !metadata.genres.contains(&genre) {
+                        metadata.genres.push(genre);
+                    }
+                }
+            }
+            "identifier" => match attribute("scheme").as_deref() {
+                Some("isbn") => metadata.isbn = Some(value),
+                Some("asin") | Some("audible") | Some("amazon") => metadata.asin = Some(value),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    for meta in OPF_META.find_iter(text) {
+        let attributes = xml_attributes(meta.as_str());
+        let get = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        match (get("name"), get("content")) {
+            (Some("calibre:series"), Some(series)) => metadata.series = non_empty(series),
+            (Some("calibre:series_index"), Some(index)) => {
+                metadata.series_index = parse_index_tag(index)
+            }
+            _ => {}
+        }
+    }
+    metadata
+}
+
+/// Reads an Audiobookshelf `metadata.json`, `None` when it is not valid JSON.
+fn parse_abs_metadata(text: &str) -> Option<WorkMetadata> {
+    let json: JsonValue = serde_json::from_str(text).ok()?;
+    let string = |key: &str| json.get(key).and_then(|x| x.as_str()).and_then(non_empty);
+    let strings = |key: &str| -> Vec<String> {
+        json.get(key)
+            .and_then(|x| x.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|x| x.as_str())
+                    .filter_map(non_empty)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut metadata = WorkMetadata {
+        title: string("title").unwrap_or_default(),
+        subtitle: string("subtitle"),
+        authors: strings("authors"),
+        narrators: strings("narrators"),
+        description: string("description"),
+        publisher: string("publisher"),
+        published_year: string("publishedYear")
+            .or_else(|| string("publishedDate"))
+            .and_then(|x| parse_year(&x)),
+        language: string("language"),
+        isbn: string("isbn"),
+        asin: string("asin"),
+        genres: normalise_genres(&strings("genres").join(";")),
+        ..Default::default()
+    };
+    if let Some(series) = strings("series").first() {
+        set_series(&mut metadata, series);
+    }
+    Some(metadata)
+}
+
+/// Sets the series from values such as "Series Name #2" that carry the index.
+fn set_series(metadata: &mut WorkMetadata, value: &str) {
+    match SERIES_WITH_INDEX.captures(value) {
+        Some(captures) => {
+            metadata.series = non_empty(captures[1].trim());
+            metadata.series_index = captures[2].parse().ok();
+        }
+        None => metadata.series = non_empty(value),
+    }
+}
+
+fn xml_attributes(text: &str) -> Vec<(String, String)> {
+    XML_ATTRIBUTE
+        .captures_iter(text)
+        .map(|x| (x[1].to_owned(), xml_unescape(&x[2])))
+        .collect()
+}
+
+/// Text of an element, OPF descriptions often hold escaped HTML which is
+/// stripped down to its text.
+fn xml_text(text: &str) -> String {
+    let text = xml_unescape(text.trim());
+    XML_TAG.replace_all(&text, "").trim().to_owned()
+}
+
+fn xml_unescape(text: &str) -> String {
+    XML_ENTITY
+        .replace_all(text, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let character = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|x| u32::from_str_radix(x, 16))
+                    .or_else(|| entity.strip_prefix('#').map(|x| x.parse()))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            character.map_or_else(|| captures[0].to_owned(), String::from)
+        })
+        .into_owned()
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const CALIBRE_OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Lies &amp; Thieves</dc:title>
+    <dc:creator opf:role="aut" opf:file-as="Lynch, Scott">Scott Lynch</dc:creator>
+    <dc:creator opf:role="nrt">Michael Page</dc:creator>
+    <dc:description>&lt;p&gt;A thief&#39;s tale.&lt;/p&gt;</dc:description>
+    <dc:publisher>Tantor</dc:publisher>
+    <dc:date>2006-06-27T00:00:00+00:00</dc:date>
+    <dc:language>eng</dc:language>
+    <dc:subject>Fantasy</dc:subject>
+    <dc:subject>heist</dc:subject>
+    <dc:identifier opf:scheme="ISBN">9780553588941</dc:identifier>
+    <meta name="calibre:series" content="Gentleman Bastard"/>
+    <meta name="calibre:series_index" content="1.0"/>
+  </metadata>
+</package>
+"#;
+
+    const ABS_METADATA: &str = r#"{
+  "tags": [],
+  "chapters": [],
+  "title": "Red Seas Under Red Skies",
+  "subtitle": null,
+  "authors": ["Scott Lynch"],
+  "narrators": ["Michael Page"],
+  "series": ["Gentleman Bastard #2"],
+  "genres": ["Fantasy", "adventure"],
+  "publishedYear": "2007",
+  "publishedDate": null,
+  "publisher": null,
+  "description": "Locke and Jean head to sea.",
+  "isbn": null,
+  "asin": "B002UZJGYY",
+  "language": "English",
+  "explicit": false
+}"#;
+
+    const LIBATION_NFO: &str = "General Information
+===================
+ Title:                  The Republic of Thieves
+ Author:                 Scott Lynch
+ Read By:                Michael Page
+ Copyright:              2013 Scott Lynch
+ Genre:                  Fantasy
+ Publisher:              Tantor Audio
+ Series Name:            Gentleman Bastard
+ Series Sequence:        3
+ Release Date:           2014-10-08
+ Language:               English
+
+Book Description
+================
+Locke and Jean are back.
+Sabetha returns.
+";
+
+    /// Writes `files` into an empty folder below the temp dir.
+    fn fixture_folder(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!(
+            "audiobookplayer-sidecar-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        for (file, contents) in files {
+            fs::write(folder.join(file), contents).unwrap();
+        }
+        folder
+    }
+
+    #[test]
+    fn parses_calibre_opf() {
+        let metadata = parse_opf(CALIBRE_OPF);
+        assert_eq!(metadata.title, "Lies & Thieves");
+        assert_eq!(metadata.authors, vec!["Scott Lynch"]);
+        assert_eq!(metadata.narrators, vec!["Michael Page"]);
+        assert_eq!(metadata.description.as_deref(), Some("A thief's tale."));
+        assert_eq!(metadata.publisher.as_deref(), Some("Tantor"));
+        assert_eq!(metadata.published_year, Some(2006));
+        assert_eq!(metadata.language.as_deref(), Some("eng"));
+        assert_eq!(metadata.genres, vec!["Fantasy", "Heist"]);
+        assert_eq!(metadata.isbn.as_deref(), Some("9780553588941"));
+        assert_eq!(metadata.series.as_deref(), Some("Gentleman Bastard"));
+        assert_eq!(metadata.series_index, Some(1.0));
+    }
+
+    #[test]
+    fn parses_abs_metadata() {
+        let metadata = parse_abs_metadata(ABS_METADATA).unwrap();
+        assert_eq!(metadata.title, "Red Seas Under Red Skies");
+        assert_eq!(metadata.subtitle, None);
+        assert_eq!(metadata.authors, vec!["Scott Lynch"]);
+        assert_eq!(metadata.narrators, vec!["Michael Page"]);
+        assert_eq!(metadata.series.as_deref(), Some("Gentleman Bastard"));
+        assert_eq!(metadata.series_index, Some(2.0));
+        assert_eq!(metadata.genres, vec!["Fantasy", "Adventure"]);
+        assert_eq!(metadata.published_year, Some(2007));
+        assert_eq!(metadata.publisher, None);
+        assert_eq!(metadata.asin.as_deref(), Some("B002UZJGYY"));
+        assert_eq!(metadata.language.as_deref(), Some("English"));
+        assert!(parse_abs_metadata("not json").is_none());
+    }
+
+    #[test]
+    fn parses_libation_nfo() {
+        let metadata = parse_nfo(LIBATION_NFO);
+        assert_eq!(metadata.title, "The Republic of Thieves");
+        assert_eq!(metadata.authors, vec!["Scott Lynch"]);
+        assert_eq!(metadata.narrators, vec!["Michael Page"]);
+        assert_eq!(metadata.copyright.as_deref(), Some("2013 Scott Lynch"));
+        assert_eq!(metadata.genres, vec!["Fantasy"]);
+        assert_eq!(metadata.publisher.as_deref(), Some("Tantor Audio"));
+        assert_eq!(metadata.series.as_deref(), Some("Gentleman Bastard"));
+        assert_eq!(metadata.series_index, Some(3.0));
+        assert_eq!(metadata.published_year, Some(2014));
+        assert_eq!(metadata.language.as_deref(), Some("English"));
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("Locke and Jean are back.\nSabetha returns.")
+        );
+    }
+
+    #[test]
+    fn reads_description_and_narrator_files() {
+        let folder = fixture_folder(
+            "txt",
+            &[
+                (DESCRIPTION_FILE, "  A thief's tale.\n"),
+                (NARRATOR_FILE, "Michael Page & Someone Else\n"),
+            ],
+        );
+        let metadata = read_sidecars(&folder).unwrap();
+        assert_eq!(metadata.description.as_deref(), Some("A thief's tale."));
+        assert_eq!(metadata.narrators, vec!["Michael Page", "Someone Else"]);
+        assert!(metadata.title.is_empty());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn reads_nothing_without_sidecars() {
+        let folder = fixture_folder("empty", &[]);
+        assert!(read_sidecars(&folder).is_none());
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn sidecars_take_precedence_in_order() {
+        let folder = fixture_folder(
+            "precedence",
+            &[
+                (ABS_METADATA_FILE, ABS_METADATA),
+                (OPF_FILE, CALIBRE_OPF),
+                ("release.nfo", LIBATION_NFO),
+                (DESCRIPTION_FILE, "Text file description"),
+                (NARRATOR_FILE, "Text File Narrator"),
+            ],
+        );
+        let metadata = read_sidecars(&folder).unwrap();
+        // metadata.json over the OPF and the NFO
+        assert_eq!(metadata.title, "Red Seas Under Red Skies");
+        assert_eq!(metadata.series_index, Some(2.0));
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("Locke and Jean head to sea.")
+        );
+        // the OPF over the NFO where metadata.json has no value
+        assert_eq!(metadata.publisher.as_deref(), Some("Tantor"));
+        assert_eq!(metadata.isbn.as_deref(), Some("9780553588941"));
+        // the NFO over the text files, and where nothing above has a value
+        assert_eq!(metadata.narrators, vec!["Michael Page"]);
+        assert_eq!(metadata.copyright.as_deref(), Some("2013 Scott Lynch"));
+        fs::remove_dir_all(&folder).unwrap();
+
+        let folder = fixture_folder(
+            "precedence-nfo",
+            &[
+                ("release.nfo", LIBATION_NFO),
+                (DESCRIPTION_FILE, "Text file description"),
+                (NARRATOR_FILE, "Text File Narrator"),
+            ],
+        );
+        let metadata = read_sidecars(&folder).unwrap();
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("Locke and Jean are back.\nSabetha returns.")
+        );
+        assert_eq!(metadata.narrators, vec!["Michael Page"]);
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
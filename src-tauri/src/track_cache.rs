@@ -0,0 +1,171 @@
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::time::UNIX_EPOCH;
+use surrealdb::sql::{Thing, Value};
+
+use crate::types::{TrackMetadata, TrackReadError, WorkAudio, WorkTracks};
+use crate::utils::{from_value, into_iter_objects, read_file_metadata, stored_path, to_value};
+use crate::DB;
+use crate::SES;
+
+/// Bumped whenever `TrackMetadata` gains fields, older entries are read again.
+const TRACK_CACHE_VERSION: u32 = 1;
+
+/// Track metadata saved in the database together with the modification time
+/// and size of the file it was read from.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedTrack {
+    #[serde(default)]
+    version: u32,
+    path: String,
+    modified: u64,
+    size: u64,
+    metadata: TrackMetadata,
+}
+
+/// Returns the metadata of the given audio files, read from the database when
+/// the file is unchanged since it was cached and from the file otherwise.
+/// Files that fail to read don't stop the others from loading.
+pub async fn load_track_metadata(paths: &[String]) -> WorkTracks {
+    let mut cached = load_cached_tracks(paths).await.unwrap_or_else(|err| {
+        error!("{}", err);
+        HashMap::new()
+    });
+
+    let mut result = WorkTracks::default();
+    for path in paths {
+        let stamp = file_stamp(path);
+        if let (Some(track), Some((modified, size))) = (cached.remove(path), stamp) {
+            if track.version == TRACK_CACHE_VERSION
+                && track.modified == modified
+                && track.size == size
+            {
+                result.tracks.push(track.metadata);
+                continue;
+            }
+        }
+
+        let read = match stamp {
+            Some(_) => {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || read_file_metadata(path))
+                    .await
+                    .unwrap_or_else(|err| Err(format!("failed to read audio file: {}", err)))
+            }
+            None => Err("file does not exist".into()),
+        };
+        match read {
+            Ok(metadata) => {
+                if let Some((modified, size)) = stamp {
+                    let track = CachedTrack {
+                        version: TRACK_CACHE_VERSION,
+                        path: path.clone(),
+                        modified,
+                        size,
+                        metadata: metadata.clone(),
+                    };
+                    if let Err(err) = save_cached_track(&track).await {
+                        error!("{}", err);
+                    }
+                }
+                result.tracks.push(metadata);
+            }
+            Err(message) => {
+                warn!("Failed to read metadata of {}: {}", path, message);
+                result.tracks.push(unreadable_track(path));
+                result.errors.push(TrackReadError {
+                    path: path.clone(),
+                    message,
+                });
+            }
+        }
+    }
+
+    result
+}
+
+/// Combines the audio properties of a work's tracks.
+pub fn summarise_audio(tracks: &[TrackMetadata]) -> WorkAudio {
+    let mut audio = WorkAudio::default();
+    for properties in tracks.iter().map(|x| &x.properties) {
+        if !properties.codec.is_empty() && !audio.codecs.contains(&properties.codec) {
+            audio.codecs.push(properties.codec.clone());
+        }
+        if !properties.container.is_empty() && !audio.containers.contains(&properties.container) {
+            audio.containers.push(properties.container.clone());
+        }
+        if let Some(bitrate) = properties.bitrate {
+            audio.min_bitrate = Some(audio.min_bitrate.map_or(bitrate, |x| x.min(bitrate)));
+            audio.max_bitrate = Some(audio.max_bitrate.map_or(bitrate, |x| x.max(bitrate)));
+        }
+        audio.sample_rate = audio.sample_rate.max(properties.sample_rate);
+        audio.channels = audio.channels.max(properties.channels);
+        audio.bit_depth = audio.bit_depth.max(properties.bit_depth);
+        audio.size += properties.file_size;
+        audio.drm |= properties.drm;
+    }
+    audio
+}
+
+/// Placeholder for a file that could not be read, keeps the tracks lined up
+/// with the work's audio files.
+fn unreadable_track(path: &str) -> TrackMetadata {
+    TrackMetadata {
+        path: path.to_owned(),
+        ..Default::default()
+    }
+}
+
+fn file_stamp(path: &str) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(stored_path(path)).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default();
+    Some((modified, metadata.len()))
+}
+
+/// Cached tracks are keyed by a hash of their path, so looking them up never
+/// scans the table.
+fn track_id(path: &str) -> Value {
+    let hash = Sha256::digest(path.as_bytes())
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    Value::Thing(Thing::from(("track_metadata".to_owned(), hash)))
+}
+
+async fn load_cached_tracks(paths: &[String]) -> Result<HashMap<String, CachedTrack>, String> {
+    let ids = paths.iter().map(|x| track_id(x)).collect::<Vec<Value>>();
+    let data: BTreeMap<String, Value> = BTreeMap::from([("ids".into(), Value::Array(ids.into()))]);
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute("SELECT * FROM $ids", &SES, Some(data), false)
+        .await
+        .map_err(|err| format!("failed to load track metadata: {}", err))?;
+
+    Ok(into_iter_objects(result)?
+        .filter_map(Result::ok)
+        .filter_map(|x| from_value::<CachedTrack>(Value::Object(x)).ok())
+        .map(|x| (x.path.clone(), x))
+        .collect())
+}
+
+async fn save_cached_track(track: &CachedTrack) -> Result<(), String> {
+    let data: BTreeMap<String, Value> = BTreeMap::from([
+        ("id".into(), track_id(&track.path)),
+        ("track".into(), to_value(track)?),
+    ]);
+    DB.get()
+        .expect("DB does not exist")
+        .execute("UPDATE $id CONTENT $track", &SES, Some(data), false)
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("failed to save track metadata: {}", err))
+}
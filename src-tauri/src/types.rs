@@ -1,7 +1,8 @@
+use lofty::ItemKey;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct TrackMetadata {
     pub path: String,
     pub track_title: String,
@@ -9,6 +10,53 @@ pub struct TrackMetadata {
     pub album_title: String,
     pub duration: Duration,
     pub chapters: Vec<Chapter>,
+    #[serde(default)]
+    pub properties: AudioProperties,
+}
+
+/// Technical properties of an audio file, `bitrate` is in kbps.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AudioProperties {
+    pub codec: String,
+    pub container: String,
+    pub bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub bit_depth: Option<u8>,
+    pub file_size: u64,
+    /// Encrypted files, such as Audible AAX, which can't be played.
+    pub drm: bool,
+}
+
+/// `AudioProperties` of a work's audio files combined, bitrates keep the
+/// lowest and highest file, the other values the highest.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct WorkAudio {
+    pub codecs: Vec<String>,
+    pub containers: Vec<String>,
+    pub min_bitrate: Option<u32>,
+    pub max_bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub bit_depth: Option<u8>,
+    pub size: u64,
+    pub drm: bool,
+}
+
+/// Narrows the library down, every condition that is set must hold.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct LibraryFilter {
+    pub codec: Option<String>,
+    pub container: Option<String>,
+    pub min_bitrate: Option<u32>,
+    pub max_bitrate: Option<u32>,
+    pub channels: Option<u8>,
+    pub drm: Option<bool>,
+    pub genre: Option<String>,
+    pub min_year: Option<i32>,
+    pub max_year: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -28,12 +76,292 @@ pub struct Work {
     pub id: String,
     pub name: String,
     pub author: String,
+    pub authors: Vec<Author>,
+    pub narrators: Vec<Author>,
     pub series: Option<String>,
+    pub series_index: Option<f64>,
 
+    /// Folder of the work as stored, see `utils::path_to_stored`.
     pub path: String,
+    /// `path` for showing, not saved.
+    #[serde(default)]
+    pub display_path: String,
+    /// Library root the work was scanned from.
+    pub root: String,
+    /// Set while the library root of the work is not mounted.
+    pub offline: bool,
+    /// Set when the files of the work vanished from its mounted root.
+    pub missing: bool,
     pub files: Vec<String>,
     pub image_files: Vec<String>,
     pub audio_files: Vec<String>,
+    /// Set once the user reordered `audio_files`, scans then keep that order.
+    pub custom_order: bool,
+    pub duration: Duration,
+    /// `fingerprint::fingerprint_file` of each audio file, used to follow the
+    /// work when its folder is moved or renamed.
+    pub fingerprints: Vec<String>,
+    pub cover: Option<CoverArt>,
+    pub audio: WorkAudio,
+
+    pub metadata: WorkMetadata,
+    /// Disagreements between folder names and tags found by the hybrid scanner.
+    pub review: Vec<ReviewFlag>,
+}
+
+impl Work {
+    /// The edited title, or the folder name for works without one.
+    pub fn title(&self) -> String {
+        if self.metadata.title.is_empty() {
+            self.name.clone()
+        } else {
+            self.metadata.title.clone()
+        }
+    }
+
+    /// The edited authors, or the scanned author for works without any.
+    pub fn author_names(&self) -> Vec<String> {
+        if !self.metadata.authors.is_empty() {
+            self.metadata.authors.clone()
+        } else if !self.author.is_empty() {
+            vec![self.author.clone()]
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// A cover in the cover cache, see `covers::resolve_cover`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CoverArt {
+    /// Sha-256 of the image, the cached files are named after it.
+    pub hash: String,
+    pub path: String,
+    pub small: Option<String>,
+    pub medium: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReviewFlag {
+    pub field: MetadataField,
+    pub folder: String,
+    pub tags: String,
+}
+
+/// A record of the `authors` table, linked to works through the `wrote` and
+/// `narrated` relations.
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Author {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthorSummary {
+    pub id: String,
+    pub name: String,
+    pub book_count: usize,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeriesSummary {
+    pub name: String,
+    pub authors: Vec<String>,
+    pub volumes: Vec<Work>,
+    /// Number of volumes listened to the end.
+    pub completed: usize,
+    /// Share of the whole series listened to, between 0 and 1.
+    pub progress: f64,
+}
+
+/// User editable description of a work, kept on the work record so it
+/// survives rescans. Fields listed in `locked` are never overwritten by the
+/// scanners.
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct WorkMetadata {
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub authors: Vec<String>,
+    pub narrators: Vec<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
+    pub description: Option<String>,
+    pub publisher: Option<String>,
+    pub published_year: Option<i32>,
+    pub language: Option<String>,
+    pub copyright: Option<String>,
+    pub isbn: Option<String>,
+    pub asin: Option<String>,
+    pub genres: Vec<String>,
+
+    pub locked: Vec<MetadataField>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MetadataField {
+    Title,
+    Subtitle,
+    Authors,
+    Narrators,
+    Series,
+    SeriesIndex,
+    Description,
+    Publisher,
+    PublishedYear,
+    Language,
+    Copyright,
+    Isbn,
+    Asin,
+    Genres,
+}
+
+impl WorkMetadata {
+    pub fn is_locked(&self, field: MetadataField) -> bool {
+        self.locked.contains(&field)
+    }
+
+    /// Takes every non-empty value found by a scan, unless the field is locked.
+    pub fn merge_scanned(&mut self, scanned: WorkMetadata) {
+        let locked = self.locked.clone();
+        let open = |field: MetadataField| !locked.contains(&field);
+
+        if open(MetadataField::Title) && !scanned.title.is_empty() {
+            self.title = scanned.title;
+        }
+        if open(MetadataField::Subtitle) && scanned.subtitle.is_some() {
+            self.subtitle = scanned.subtitle;
+        }
+        if open(MetadataField::Authors) && !scanned.authors.is_empty() {
+            self.authors = scanned.authors;
+        }
+        if open(MetadataField::Narrators) && !scanned.narrators.is_empty() {
+            self.narrators = scanned.narrators;
+        }
+        if open(MetadataField::Series) && scanned.series.is_some() {
+            self.series = scanned.series;
+        }
+        if open(MetadataField::SeriesIndex) && scanned.series_index.is_some() {
+            self.series_index = scanned.series_index;
+        }
+        if open(MetadataField::Description) && scanned.description.is_some() {
+            self.description = scanned.description;
+        }
+        if open(MetadataField::Publisher) && scanned.publisher.is_some() {
+            self.publisher = scanned.publisher;
+        }
+        if open(MetadataField::PublishedYear) && scanned.published_year.is_some() {
+            self.published_year = scanned.published_year;
+        }
+        if open(MetadataField::Language) && scanned.language.is_some() {
+            self.language = scanned.language;
+        }
+        if open(MetadataField::Copyright) && scanned.copyright.is_some() {
+            self.copyright = scanned.copyright;
+        }
+        if open(MetadataField::Isbn) && scanned.isbn.is_some() {
+            self.isbn = scanned.isbn;
+        }
+        if open(MetadataField::Asin) && scanned.asin.is_some() {
+            self.asin = scanned.asin;
+        }
+        if open(MetadataField::Genres) && !scanned.genres.is_empty() {
+            self.genres = scanned.genres;
+        }
+    }
+
+    /// Takes the synopsis, genres and publication details of `tags` where
+    /// `self` has none, for works named from their folders. The series index
+    /// of the tags is taken too when the folders named a series but not the
+    /// volume.
+    pub fn fill_descriptive(&mut self, tags: WorkMetadata) {
+        if self.series.is_some() {
+            self.series_index = self.series_index.or(tags.series_index);
+        }
+        self.description = self.description.take().or(tags.description);
+        self.publisher = self.publisher.take().or(tags.publisher);
+        self.published_year = self.published_year.or(tags.published_year);
+        self.language = self.language.take().or(tags.language);
+        self.copyright = self.copyright.take().or(tags.copyright);
+        if self.genres.is_empty() {
+            self.genres = tags.genres;
+        }
+    }
+
+    /// Takes the values of the fields locked in `other` and locks them here
+    /// too, carrying manual edits over to a freshly scanned work.
+    pub fn take_locked(&mut self, other: &WorkMetadata) {
+        for field in other.locked.iter().copied() {
+            match field {
+                MetadataField::Title => self.title = other.title.clone(),
+                MetadataField::Subtitle => self.subtitle = other.subtitle.clone(),
+                MetadataField::Authors => self.authors = other.authors.clone(),
+                MetadataField::Narrators => self.narrators = other.narrators.clone(),
+                MetadataField::Series => self.series = other.series.clone(),
+                MetadataField::SeriesIndex => self.series_index = other.series_index,
+                MetadataField::Description => self.description = other.description.clone(),
+                MetadataField::Publisher => self.publisher = other.publisher.clone(),
+                MetadataField::PublishedYear => self.published_year = other.published_year,
+                MetadataField::Language => self.language = other.language.clone(),
+                MetadataField::Copyright => self.copyright = other.copyright.clone(),
+                MetadataField::Isbn => self.isbn = other.isbn.clone(),
+                MetadataField::Asin => self.asin = other.asin.clone(),
+                MetadataField::Genres => self.genres = other.genres.clone(),
+            }
+            if !self.is_locked(field) {
+                self.locked.push(field);
+            }
+        }
+    }
+
+    /// Lists the fields whose values differ between `self` and `other`.
+    pub fn changed_fields(&self, other: &WorkMetadata) -> Vec<MetadataField> {
+        let mut changed = vec![];
+        if self.title != other.title {
+            changed.push(MetadataField::Title);
+        }
+        if self.subtitle != other.subtitle {
+            changed.push(MetadataField::Subtitle);
+        }
+        if self.authors != other.authors {
+            changed.push(MetadataField::Authors);
+        }
+        if self.narrators != other.narrators {
+            changed.push(MetadataField::Narrators);
+        }
+        if self.series != other.series {
+            changed.push(MetadataField::Series);
+        }
+        if self.series_index != other.series_index {
+            changed.push(MetadataField::SeriesIndex);
+        }
+        if self.description != other.description {
+            changed.push(MetadataField::Description);
+        }
+        if self.publisher != other.publisher {
+            changed.push(MetadataField::Publisher);
+        }
+        if self.published_year != other.published_year {
+            changed.push(MetadataField::PublishedYear);
+        }
+        if self.language != other.language {
+            changed.push(MetadataField::Language);
+        }
+        if self.copyright != other.copyright {
+            changed.push(MetadataField::Copyright);
+        }
+        if self.isbn != other.isbn {
+            changed.push(MetadataField::Isbn);
+        }
+        if self.asin != other.asin {
+            changed.push(MetadataField::Asin);
+        }
+        if self.genres != other.genres {
+            changed.push(MetadataField::Genres);
+        }
+        changed
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,14 +370,298 @@ pub struct ClearDatabaseError;
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoadWorksError;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ReadFileMetadataError {}
+/// Track metadata of a work's audio files. `tracks` has one entry per audio
+/// file in play order, files that could not be read get an empty entry and
+/// the reason in `errors`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WorkTracks {
+    pub tracks: Vec<TrackMetadata>,
+    pub errors: Vec<TrackReadError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrackReadError {
+    pub path: String,
+    pub message: String,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
 pub struct Settings {
+    /// Single library folder of settings saved before `library_roots`.
     pub library_location: String,
     pub library_style: LibraryStyle,
-    // pub metadata_template: MetadataTemplate,
+    pub library_roots: Vec<LibraryRoot>,
+    pub metadata_template: MetadataTemplate,
+    /// Folder layouts tried in order by the folder scanner, see
+    /// `folder_template::FolderTemplate`.
+    pub folder_templates: Vec<String>,
+    pub metadata_providers: MetadataProviderSettings,
+}
+
+/// Base URLs of the online metadata providers, an empty URL turns the
+/// provider off.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct MetadataProviderSettings {
+    pub open_library_url: String,
+    pub open_library_covers_url: String,
+    pub google_books_url: String,
+}
+
+impl Default for MetadataProviderSettings {
+    fn default() -> Self {
+        Self {
+            open_library_url: "https://openlibrary.org".into(),
+            open_library_covers_url: "https://covers.openlibrary.org".into(),
+            google_books_url: "https://www.googleapis.com/books/v1".into(),
+        }
+    }
+}
+
+/// What to look a book up by online, empty fields are left out.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct MetadataQuery {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub isbn: Option<String>,
+}
+
+/// A possible match for a book returned by an online metadata provider.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MetadataCandidate {
+    pub provider: String,
+    /// Identifier of the book at the provider.
+    pub id: String,
+    pub metadata: WorkMetadata,
+    pub cover_url: Option<String>,
+}
+
+impl Settings {
+    /// The configured library roots, falling back to `library_location` for
+    /// settings that have none.
+    pub fn roots(&self) -> Vec<LibraryRoot> {
+        if !self.library_roots.is_empty() || self.library_location.is_empty() {
+            return self.library_roots.clone();
+        }
+        vec![LibraryRoot {
+            path: self.library_location.clone(),
+            style: self.library_style.clone(),
+            ..Default::default()
+        }]
+    }
+}
+
+/// A folder holding books, such as an internal disk, a NAS mount or a USB
+/// drive.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LibraryRoot {
+    pub path: String,
+    pub style: LibraryStyle,
+    /// Folder layouts for this root, `Settings::folder_templates` when empty.
+    pub folder_templates: Vec<String>,
+    pub enabled: bool,
+}
+
+impl Default for LibraryRoot {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            style: LibraryStyle::default(),
+            folder_templates: vec![],
+            enabled: true,
+        }
+    }
+}
+
+/// Payload of the `scan_progress` event. `completed` and `total` count the
+/// items of the current phase, `failed` counts failures over the whole scan.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ScanProgress {
+    pub phase: ScanPhase,
+    /// Library root being scanned.
+    pub root: Option<String>,
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub current_path: Option<String>,
+    pub eta: Option<Duration>,
+    /// Set on the event reporting a failed item.
+    pub failure: Option<ScanFailure>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum ScanPhase {
+    #[default]
+    FindingFiles,
+    ReadingFiles,
+    Saving,
+    Complete,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScanFailure {
+    pub path: String,
+    pub kind: ScanFailureKind,
+    /// The underlying io or tag reading error, empty in older reports.
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ScanFailureKind {
+    FileRead,
+    TagRead,
+    AuthorRead,
+    AlbumRead,
+    Save,
+    /// A folder or directory entry could not be read.
+    DirectoryRead,
+    /// The path is not valid UTF-8, only found in reports from before such
+    /// paths were stored.
+    InvalidPath,
+}
+
+/// Conditions that stop a scan before it reads any files.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ScanError {
+    AlreadyRunning,
+    NoLibraryRoots,
+    Settings(String),
+}
+
+/// Outcome of one scan run, kept so problem files can be fixed later.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScanReport {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    /// Seconds since the unix epoch.
+    pub started_at: u64,
+    pub duration: Duration,
+    /// Style forced on every root, `None` when each root used its own.
+    pub style: Option<LibraryStyle>,
+    pub files_seen: usize,
+    pub works_added: usize,
+    /// Works already in the library refreshed by the scan.
+    #[serde(default)]
+    pub works_updated: usize,
+    pub cancelled: bool,
+    pub failures: Vec<ScanFailure>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScanReportSummary {
+    pub id: String,
+    pub started_at: u64,
+    pub duration: Duration,
+    pub style: Option<LibraryStyle>,
+    pub files_seen: usize,
+    pub works_added: usize,
+    pub works_updated: usize,
+    pub cancelled: bool,
+    pub failed: usize,
+}
+
+/// Works that are likely the same book, such as an MP3 and an M4B copy.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DuplicateGroup {
+    pub works: Vec<Work>,
+    /// Between 0 and 1, the weakest match between two works of the group.
+    pub confidence: f64,
+    pub reasons: Vec<DuplicateReason>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum DuplicateReason {
+    Title,
+    Author,
+    Duration,
+    /// The works share audio files.
+    Fingerprint,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FolderTemplatePreview {
+    pub path: String,
+    pub matched: bool,
+    pub metadata: WorkMetadata,
+}
+
+/// How a backup is restored into the database.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum RestoreMode {
+    /// Empties the library and settings first.
+    Replace,
+    /// Keeps the current library, works already in it only take the locked
+    /// metadata, file order and review flags of their backup, and resume
+    /// times keep the furthest position.
+    Merge,
+}
+
+/// First file of a backup archive, checked before anything is restored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupManifest {
+    pub format: String,
+    pub version: u32,
+    pub created_at: u64,
+    pub app_version: String,
+}
+
+/// A resume position keyed by what still identifies the work on another
+/// machine, record ids are generated per database.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ProgressEntry {
+    /// Folder of the work relative to its library root, with `/` separators.
+    pub relative_path: String,
+    pub title: String,
+    pub author: String,
+    pub fingerprints: Vec<String>,
+    pub position: f64,
+}
+
+/// File written by `export_progress`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProgressExport {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: u64,
+    pub entries: Vec<ProgressEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProgressImportReport {
+    pub matched: usize,
+    /// Entries no work in the library was found for.
+    pub unmatched: Vec<ProgressEntry>,
+}
+
+/// Sidecar formats works can be exported to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum ExportFormat {
+    /// Audiobookshelf's `metadata.json`.
+    Audiobookshelf,
+    /// A Calibre style `metadata.opf`.
+    Opf,
+}
+
+/// Outcome of an export, a work failing doesn't stop the others.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ExportReport {
+    /// Files written.
+    pub written: Vec<String>,
+    /// Sidecars already in a work's folder, left as they were.
+    pub skipped: Vec<String>,
+    pub failures: Vec<ExportFailure>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportFailure {
+    pub path: String,
+    pub message: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -57,26 +669,184 @@ pub enum LibraryStyle {
     #[default]
     Folder,
     Metadata,
+    Hybrid,
 }
 
+/// Where the metadata scanner reads a field from, either a generic lofty
+/// `ItemKey` by variant name or a freeform tag such as `SERIES-PART`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum TagSource {
+    Key(String),
+    Custom(String),
+}
+
+impl TagSource {
+    fn key(name: &str) -> Self {
+        Self::Key(name.to_owned())
+    }
+
+    fn custom(name: &str) -> Self {
+        Self::Custom(name.to_owned())
+    }
+
+    /// The lofty key named by a `Key` source, `None` for freeform tags and
+    /// names lofty does not know.
+    pub fn item_key(&self) -> Option<ItemKey> {
+        let TagSource::Key(name) = self else {
+            return None;
+        };
+        Some(match name.as_str() {
+            "AlbumTitle" => ItemKey::AlbumTitle,
+            "SetSubtitle" => ItemKey::SetSubtitle,
+            "ShowName" => ItemKey::ShowName,
+            "ContentGroup" => ItemKey::ContentGroup,
+            "TrackTitle" => ItemKey::TrackTitle,
+            "TrackSubtitle" => ItemKey::TrackSubtitle,
+            "OriginalAlbumTitle" => ItemKey::OriginalAlbumTitle,
+            "OriginalArtist" => ItemKey::OriginalArtist,
+            "OriginalLyricist" => ItemKey::OriginalLyricist,
+            "AlbumTitleSortOrder" => ItemKey::AlbumTitleSortOrder,
+            "AlbumArtistSortOrder" => ItemKey::AlbumArtistSortOrder,
+            "TrackTitleSortOrder" => ItemKey::TrackTitleSortOrder,
+            "TrackArtistSortOrder" => ItemKey::TrackArtistSortOrder,
+            "ShowNameSortOrder" => ItemKey::ShowNameSortOrder,
+            "ComposerSortOrder" => ItemKey::ComposerSortOrder,
+            "AlbumArtist" => ItemKey::AlbumArtist,
+            "TrackArtist" => ItemKey::TrackArtist,
+            "Arranger" => ItemKey::Arranger,
+            "Writer" => ItemKey::Writer,
+            "Composer" => ItemKey::Composer,
+            "Conductor" => ItemKey::Conductor,
+            "Director" => ItemKey::Director,
+            "Engineer" => ItemKey::Engineer,
+            "InvolvedPeople" => ItemKey::InvolvedPeople,
+            "Lyricist" => ItemKey::Lyricist,
+            "MixDj" => ItemKey::MixDj,
+            "MixEngineer" => ItemKey::MixEngineer,
+            "MusicianCredits" => ItemKey::MusicianCredits,
+            "Performer" => ItemKey::Performer,
+            "Producer" => ItemKey::Producer,
+            "Publisher" => ItemKey::Publisher,
+            "Label" => ItemKey::Label,
+            "InternetRadioStationName" => ItemKey::InternetRadioStationName,
+            "InternetRadioStationOwner" => ItemKey::InternetRadioStationOwner,
+            "Remixer" => ItemKey::Remixer,
+            "DiscNumber" => ItemKey::DiscNumber,
+            "DiscTotal" => ItemKey::DiscTotal,
+            "TrackNumber" => ItemKey::TrackNumber,
+            "TrackTotal" => ItemKey::TrackTotal,
+            "Popularimeter" => ItemKey::Popularimeter,
+            "ParentalAdvisory" => ItemKey::ParentalAdvisory,
+            "RecordingDate" => ItemKey::RecordingDate,
+            "Year" => ItemKey::Year,
+            "OriginalReleaseDate" => ItemKey::OriginalReleaseDate,
+            "ISRC" => ItemKey::ISRC,
+            "Barcode" => ItemKey::Barcode,
+            "CatalogNumber" => ItemKey::CatalogNumber,
+            "Work" => ItemKey::Work,
+            "Movement" => ItemKey::Movement,
+            "MovementNumber" => ItemKey::MovementNumber,
+            "MovementTotal" => ItemKey::MovementTotal,
+            "FlagCompilation" => ItemKey::FlagCompilation,
+            "FlagPodcast" => ItemKey::FlagPodcast,
+            "FileType" => ItemKey::FileType,
+            "FileOwner" => ItemKey::FileOwner,
+            "TaggingTime" => ItemKey::TaggingTime,
+            "Length" => ItemKey::Length,
+            "OriginalFileName" => ItemKey::OriginalFileName,
+            "OriginalMediaType" => ItemKey::OriginalMediaType,
+            "EncodedBy" => ItemKey::EncodedBy,
+            "EncoderSoftware" => ItemKey::EncoderSoftware,
+            "EncoderSettings" => ItemKey::EncoderSettings,
+            "EncodingTime" => ItemKey::EncodingTime,
+            "ReplayGainAlbumGain" => ItemKey::ReplayGainAlbumGain,
+            "ReplayGainAlbumPeak" => ItemKey::ReplayGainAlbumPeak,
+            "ReplayGainTrackGain" => ItemKey::ReplayGainTrackGain,
+            "ReplayGainTrackPeak" => ItemKey::ReplayGainTrackPeak,
+            "AudioFileURL" => ItemKey::AudioFileURL,
+            "AudioSourceURL" => ItemKey::AudioSourceURL,
+            "CommercialInformationURL" => ItemKey::CommercialInformationURL,
+            "CopyrightURL" => ItemKey::CopyrightURL,
+            "TrackArtistURL" => ItemKey::TrackArtistURL,
+            "RadioStationURL" => ItemKey::RadioStationURL,
+            "PaymentURL" => ItemKey::PaymentURL,
+            "PublisherURL" => ItemKey::PublisherURL,
+            "Genre" => ItemKey::Genre,
+            "InitialKey" => ItemKey::InitialKey,
+            "Color" => ItemKey::Color,
+            "Mood" => ItemKey::Mood,
+            "BPM" => ItemKey::BPM,
+            "CopyrightMessage" => ItemKey::CopyrightMessage,
+            "License" => ItemKey::License,
+            "PodcastDescription" => ItemKey::PodcastDescription,
+            "PodcastSeriesCategory" => ItemKey::PodcastSeriesCategory,
+            "PodcastURL" => ItemKey::PodcastURL,
+            "PodcastReleaseDate" => ItemKey::PodcastReleaseDate,
+            "PodcastGlobalUniqueID" => ItemKey::PodcastGlobalUniqueID,
+            "PodcastKeywords" => ItemKey::PodcastKeywords,
+            "Comment" => ItemKey::Comment,
+            "Description" => ItemKey::Description,
+            "Language" => ItemKey::Language,
+            "Script" => ItemKey::Script,
+            "Lyrics" => ItemKey::Lyrics,
+            "AppleXid" => ItemKey::AppleXid,
+            "AppleId3v2ContentGroup" => ItemKey::AppleId3v2ContentGroup,
+            _ => return None,
+        })
+    }
+}
+
+/// Tag fallback order for every field read by the metadata scanner, the first
+/// source holding a value wins.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct MetadataTemplate {
-    pub author: Vec<lofty::ItemKey>,
-    pub title: Vec<lofty::ItemKey>,
+    pub author: Vec<TagSource>,
+    pub title: Vec<TagSource>,
+    pub narrator: Vec<TagSource>,
+    pub series: Vec<TagSource>,
+    pub series_index: Vec<TagSource>,
+    pub year: Vec<TagSource>,
+    pub description: Vec<TagSource>,
+    pub genre: Vec<TagSource>,
+    pub publisher: Vec<TagSource>,
+    pub copyright: Vec<TagSource>,
+    pub language: Vec<TagSource>,
 }
 
 impl Default for MetadataTemplate {
     fn default() -> Self {
         Self {
             author: vec![
-                lofty::ItemKey::TrackArtist,
-                lofty::ItemKey::OriginalArtist,
-                lofty::ItemKey::AlbumArtist,
+                TagSource::key("TrackArtist"),
+                TagSource::key("OriginalArtist"),
+                TagSource::key("AlbumArtist"),
             ],
             title: vec![
-                lofty::ItemKey::AlbumTitle,
-                lofty::ItemKey::OriginalAlbumTitle,
-                lofty::ItemKey::TrackTitle,
+                TagSource::key("AlbumTitle"),
+                TagSource::key("OriginalAlbumTitle"),
+                TagSource::key("TrackTitle"),
+            ],
+            narrator: vec![TagSource::key("Composer")],
+            series: vec![TagSource::key("Movement"), TagSource::custom("SERIES")],
+            series_index: vec![
+                TagSource::key("MovementNumber"),
+                TagSource::custom("SERIES-PART"),
+            ],
+            year: vec![
+                TagSource::key("Year"),
+                TagSource::key("RecordingDate"),
+                TagSource::key("OriginalReleaseDate"),
+            ],
+            description: vec![
+                TagSource::key("Description"),
+                TagSource::key("PodcastDescription"),
+                TagSource::key("Comment"),
             ],
+            genre: vec![TagSource::key("Genre")],
+            publisher: vec![TagSource::key("Publisher"), TagSource::key("Label")],
+            copyright: vec![TagSource::key("CopyrightMessage")],
+            language: vec![TagSource::key("Language")],
         }
     }
 }
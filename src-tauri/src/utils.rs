@@ -1,23 +1,55 @@
+use lofty::mp4::Mp4Codec;
+use lofty::mp4::Mp4File;
+use lofty::Accessor;
 use lofty::AudioFile;
+use lofty::FileType;
+use lofty::ParseOptions;
+use lofty::TaggedFile;
 use lofty::TaggedFileExt;
+use log::info;
+use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::BTreeMap;
-use std::fs::File;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use surrealdb::dbs::Response;
+use surrealdb::sql::thing;
+use surrealdb::sql::Number;
 use surrealdb::sql::Object;
 use surrealdb::sql::Value;
 
+use crate::naming::{natural_cmp, normalise_person};
 use crate::DB;
 use crate::SES;
 
-use crate::types::{Chapter, ReadFileMetadataError, TrackMetadata, Work};
+use crate::types::{AudioProperties, Author, Chapter, CoverArt, TrackMetadata, Work, WorkMetadata};
 
-pub const AUDIO_FILE_EXTENSIONS: [&str; 5] = ["mp4", "mp3", "m4a", "m4b", "wav"];
-pub const AUDIO_FILE_WITH_CHAPTERS_EXTENSIONS: [&str; 3] = ["mp4", "m4a", "m4b"];
-pub const IMAGE_FILE_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+/// "A & B", "A; B" and "A / B", a slash without spaces around it is part of
+/// names such as "AC/DC".
+static PEOPLE_SEPARATOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"[&;]|\s/\s").unwrap());
+
+/// Marks a stored path that is not valid UTF-8, followed by the hex of its
+/// raw bytes.
+const RAW_PATH_PREFIX: &str = "raw-path:";
+
+pub const AUDIO_FILE_EXTENSIONS: [&str; 7] = ["mp4", "mp3", "m4a", "m4b", "wav", "aax", "aaxc"];
+pub const AUDIO_FILE_WITH_CHAPTERS_EXTENSIONS: [&str; 5] = ["mp4", "m4a", "m4b", "aax", "aaxc"];
+/// Audible files, encrypted for the account that bought them.
+pub const DRM_FILE_EXTENSIONS: [&str; 2] = ["aax", "aaxc"];
+/// MP4 sample entries of encrypted audio.
+const DRM_CODECS: [&str; 2] = ["aavd", "drms"];
+pub const IMAGE_FILE_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "webp", "gif", "bmp", "avif"];
+
+/// Projection used by every query returning works, resolves the `wrote` and
+/// `narrated` relations into author records.
+pub const WORK_FIELDS: &str = "*, <-wrote<-authors AS authors, <-narrated<-authors AS narrators";
+pub const WORK_FETCH: &str = "FETCH authors, narrators, author";
 
 pub fn object_into_work(object: Object) -> Work {
     let series_str = object.get("series").map(|x| x.clone().as_string()).unwrap();
@@ -36,10 +68,21 @@ pub fn object_into_work(object: Object) -> Work {
     } else {
         vec![]
     };
+    let authors = object_into_authors(&object, "authors");
+    let narrators = object_into_authors(&object, "narrators");
+    let audio_files = object_into_strings(&object, "audio_files");
+    let path = object.get("path").map(|x| x.clone().as_string()).unwrap();
+
     Work {
         id: object.get("id").map(|x| x.clone().as_string()).unwrap(),
         name: object.get("name").map(|x| x.clone().as_string()).unwrap(),
-        author: if let Value::Object(sub_object) = object.get("author").unwrap() {
+        author: if !authors.is_empty() {
+            authors
+                .iter()
+                .map(|x| x.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        } else if let Some(Value::Object(sub_object)) = object.get("author") {
             sub_object
                 .get("name")
                 .map(|x| x.clone().as_string())
@@ -47,14 +90,114 @@ pub fn object_into_work(object: Object) -> Work {
         } else {
             "".to_string()
         },
+        authors,
+        narrators,
         series,
-        path: object.get("path").map(|x| x.clone().as_string()).unwrap(),
+        series_index: object.get("series_index").and_then(|x| match x {
+            Value::Number(index) => Some(index.as_float()),
+            _ => None,
+        }),
+        display_path: display_path(&path),
+        path,
+        root: object
+            .get("root")
+            .map(|x| x.clone().as_string())
+            .unwrap_or_default(),
+        offline: object
+            .get("offline")
+            .map(|x| x.is_true())
+            .unwrap_or_default(),
         files: files.clone(),
-        audio_files: get_files_by_extension(files.clone(), AUDIO_FILE_EXTENSIONS.to_vec()),
+        audio_files: if audio_files.is_empty() {
+            get_files_by_extension(files.clone(), AUDIO_FILE_EXTENSIONS.to_vec())
+        } else {
+            audio_files
+        },
+        custom_order: object
+            .get("custom_order")
+            .map(|x| x.is_true())
+            .unwrap_or_default(),
+        missing: object
+            .get("missing")
+            .map(|x| x.is_true())
+            .unwrap_or_default(),
+        fingerprints: object_into_strings(&object, "fingerprints"),
+        cover: object.get("cover").and_then(|x| from_value(x.clone()).ok()),
+        audio: object
+            .get("audio")
+            .and_then(|x| from_value(x.clone()).ok())
+            .unwrap_or_default(),
         image_files: get_files_by_extension(files, IMAGE_FILE_EXTENSIONS.to_vec()),
+        duration: object
+            .get("duration")
+            .map(|x| Duration::from_secs_f64(x.clone().as_float().max(0.0)))
+            .unwrap_or_default(),
+        metadata: object_into_work_metadata(&object),
+        review: object
+            .get("review")
+            .map(|x| from_value(x.clone()).unwrap_or_default())
+            .unwrap_or_default(),
+    }
+}
+
+pub fn object_into_strings(object: &Object, key: &str) -> Vec<String> {
+    if let Some(Value::Array(values)) = object.get(key) {
+        values.iter().map(|x| x.clone().as_string()).collect()
+    } else {
+        vec![]
+    }
+}
+
+fn object_into_authors(object: &Object, key: &str) -> Vec<Author> {
+    object
+        .get(key)
+        .map(|x| from_value(x.clone()).unwrap_or_default())
+        .unwrap_or_default()
+}
+
+pub fn object_into_work_metadata(object: &Object) -> WorkMetadata {
+    object
+        .get("metadata")
+        .map(|x| from_value(x.clone()).unwrap_or_default())
+        .unwrap_or_default()
+}
+
+/// Converts a database value into plain json, record links become their id string.
+pub fn value_into_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::None | Value::Null => serde_json::Value::Null,
+        Value::Number(Number::Int(v)) => v.into(),
+        Value::Number(v) => v.as_float().into(),
+        Value::Array(v) => v.iter().cloned().map(value_into_json).collect(),
+        Value::Object(v) => serde_json::Value::Object(
+            v.iter()
+                .map(|(key, value)| (key.clone(), value_into_json(value.clone())))
+                .collect(),
+        ),
+        v if v.is_true() => true.into(),
+        v if v.is_false() => false.into(),
+        v => v.as_string().into(),
     }
 }
 
+pub fn optional_value<T: Into<Value>>(item: Option<T>) -> Value {
+    match item {
+        Some(v) => v.into(),
+        None => Value::None,
+    }
+}
+
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(value_into_json(value))
+        .map_err(|err| format!("failed to deserialize value: {}", err))
+}
+
+pub fn to_value<T: Serialize>(item: &T) -> Result<Value, String> {
+    let json =
+        serde_json::to_string(item).map_err(|err| format!("failed to serialize value: {}", err))?;
+    surrealdb::sql::json(&json).map_err(|err| format!("failed to convert value: {}", err))
+}
+
 pub fn into_iter_objects(
     result: Vec<Response>,
 ) -> Result<impl Iterator<Item = Result<Object, String>>, String> {
@@ -74,35 +217,276 @@ pub fn into_iter_objects(
 pub fn get_files_by_extension(files: Vec<String>, extenions: Vec<&str>) -> Vec<String> {
     files
         .iter()
-        .filter(|x| {
-            let path = PathBuf::from(x);
-            path.extension().is_some()
-                && extenions.contains(&path.extension().unwrap().to_str().unwrap())
-        })
+        .filter(|x| has_extension(&stored_path(x), &extenions))
         .map(|x| x.to_owned())
         .collect::<Vec<String>>()
 }
 
-pub fn string_to_id(item: String) -> String {
-    Regex::new(r"[^a-zA-Z0-9]")
-        .unwrap()
-        .replace_all(item.as_str(), "_")
-        .to_string()
+/// Turns a path into the string kept in the database. Paths that are not
+/// UTF-8 are hex encoded behind `RAW_PATH_PREFIX`, so the stored string still
+/// points at the file, see `stored_path`.
+pub fn path_to_stored(path: &Path) -> String {
+    match path.to_str() {
+        Some(text) if !text.starts_with(RAW_PATH_PREFIX) => text.to_owned(),
+        _ => {
+            let hex = os_bytes(path.as_os_str())
+                .iter()
+                .map(|x| format!("{:02x}", x))
+                .collect::<String>();
+            format!("{}{}", RAW_PATH_PREFIX, hex)
+        }
+    }
+}
+
+/// The file system path of a string made by `path_to_stored`.
+pub fn stored_path(stored: &str) -> PathBuf {
+    stored
+        .strip_prefix(RAW_PATH_PREFIX)
+        .and_then(|hex| {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect::<Option<Vec<u8>>>()
+        })
+        .and_then(os_from_bytes)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(stored))
+}
+
+/// A stored path for showing, characters that are not UTF-8 are replaced.
+pub fn display_path(stored: &str) -> String {
+    stored_path(stored).to_string_lossy().to_string()
+}
+
+#[cfg(unix)]
+fn os_bytes(value: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    value.as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn os_from_bytes(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(OsString::from_vec(bytes))
+}
+
+#[cfg(windows)]
+fn os_bytes(value: &OsStr) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    value.encode_wide().flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(windows)]
+fn os_from_bytes(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::windows::ffi::OsStringExt;
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let wide = bytes
+        .chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .collect::<Vec<u16>>();
+    Some(OsString::from_wide(&wide))
+}
+
+/// Checks the extension of a path without requiring the path to be UTF-8.
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .map_or(false, |x| extensions.contains(&x))
+}
+
+/// Looks up a freeform tag, such as an MP4 `----:com.apple.iTunes:<name>` atom,
+/// a Vorbis comment or an ID3v2 `TXXX:<name>` frame, ignoring case.
+pub fn get_freeform_tag<'a>(tag: &'a lofty::Tag, name: &str) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    tag.items().find_map(|item| match item.key() {
+        lofty::ItemKey::Unknown(key) => {
+            let key = key.to_lowercase();
+            if key == name || key.ends_with(&format!(":{}", name)) {
+                item.value().text()
+            } else {
+                None
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Splits a tag or folder value naming several people, such as "A & B" or
+/// "A; B", into the individual names.
+pub fn split_people(value: &str) -> Vec<String> {
+    let mut people: Vec<String> = vec![];
+    for name in PEOPLE_SEPARATOR.split(value) {
+        let name = name.trim();
+        if !name.is_empty() && !people.iter().any(|x| x == name) {
+            people.push(name.to_owned());
+        }
+    }
+    people
+}
+
+/// Record id of the person `name`, shared by spellings `normalise_person`
+/// treats as the same person and distinct for any other name, whatever its
+/// script. The leading letter keeps it a plain identifier in queries.
+pub fn person_id(name: &str) -> String {
+    let hash = Sha256::digest(normalise_person(name).as_bytes())
+        .iter()
+        .take(12)
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    format!("p{}", hash)
 }
 
-pub async fn create_work(work: Work) -> Result<(), String> {
-    let ass = format!("CREATE works CONTENT {{ name: $name, author: authors:{}, series: $series, path: $path, files:$files, }}", work.author);
+pub async fn find_work_by_path(path: &str) -> Result<Option<Object>, String> {
+    let data: BTreeMap<String, Value> = BTreeMap::from([("path".into(), path.into())]);
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute(
+            "SELECT * FROM works WHERE path = $path",
+            &SES,
+            Some(data),
+            false,
+        )
+        .await
+        .map_err(|err| format!("failed to find work: {}", err))?;
+
+    into_iter_objects(result)?.next().transpose()
+}
+
+/// Finds a work whose folder no longer exists but which shares audio files
+/// with a scanned one, the scanned work is then that work moved or renamed.
+pub async fn find_moved_work(fingerprints: &[String]) -> Result<Option<Object>, String> {
+    if fingerprints.is_empty() {
+        return Ok(None);
+    }
+    let data: BTreeMap<String, Value> =
+        BTreeMap::from([("fingerprints".into(), to_value(&fingerprints)?)]);
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute(
+            "SELECT * FROM works WHERE offline != true AND fingerprints CONTAINSANY $fingerprints",
+            &SES,
+            Some(data),
+            false,
+        )
+        .await
+        .map_err(|err| format!("failed to find work: {}", err))?;
+
+    Ok(moved_candidate(
+        into_iter_objects(result)?.filter_map(Result::ok).collect(),
+    ))
+}
+
+/// The one candidate whose folder is gone from a mounted library root. Works
+/// of a root that is not mounted only look gone, and when several works share
+/// the audio files there is no telling which one moved.
+fn moved_candidate(candidates: Vec<Object>) -> Option<Object> {
+    let mut gone = candidates.into_iter().filter(|object| {
+        let text = |key: &str| {
+            object
+                .get(key)
+                .map(|x| x.clone().as_string())
+                .unwrap_or_default()
+        };
+        let offline = object.get("offline").map_or(false, |x| x.is_true());
+        let root = text("root");
+        !offline
+            && !root.is_empty()
+            && stored_path(&root).is_dir()
+            && !stored_path(&text("path")).exists()
+    });
+    match (gone.next(), gone.next()) {
+        (Some(object), None) => Some(object),
+        _ => None,
+    }
+}
+
+/// Creates the work, or refreshes the scanned fields of the work already
+/// stored for the same path while keeping its id and edited metadata. A work
+/// moved or renamed since the last scan keeps its record, and with it its
+/// listening position. Returns whether a new record was created.
+pub async fn create_work(work: Work) -> Result<bool, String> {
+    let existing = match find_work_by_path(&work.path).await? {
+        Some(object) => Some(object),
+        None => {
+            let moved = find_moved_work(&work.fingerprints).await?;
+            if let Some(object) = moved.as_ref() {
+                info!(
+                    "{} moved to {:?}",
+                    object
+                        .get("path")
+                        .map(|x| x.clone().as_string())
+                        .unwrap_or_default(),
+                    work.path
+                );
+            }
+            moved
+        }
+    };
+
+    let mut metadata = existing
+        .as_ref()
+        .map(object_into_work_metadata)
+        .unwrap_or_default();
+    metadata.merge_scanned(work.metadata);
+
+    let mut review = work.review;
+    review.retain(|x| !metadata.is_locked(x.field));
+
+    let scanned_audio_files = order_audio_files(get_files_by_extension(
+        work.files.clone(),
+        AUDIO_FILE_EXTENSIONS.to_vec(),
+    ));
+    let audio_files = match existing.as_ref() {
+        Some(object) if object.get("custom_order").map_or(false, |x| x.is_true()) => {
+            keep_custom_order(
+                object_into_strings(object, "audio_files"),
+                scanned_audio_files,
+            )
+        }
+        _ => scanned_audio_files,
+    };
+
+    // a cover matched online stays until the folder has one of its own
+    let cover = work.cover.or_else(|| {
+        existing
+            .as_ref()
+            .and_then(|x| x.get("cover"))
+            .and_then(|x| from_value::<CoverArt>(x.clone()).ok())
+    });
+
+    let fields = "name: $name, series: $series, series_index: $series_index, path: $path, \
+        root: $root, offline: false, missing: false, files: $files, \
+        audio_files: $audio_files, fingerprints: $fingerprints, cover: $cover, \
+        audio: $audio, duration: $duration, metadata: $metadata, review: $review";
+    let ass = match existing.as_ref().and_then(|x| x.get("id")) {
+        Some(id) => format!("UPDATE {} MERGE {{ {} }}", id.clone().as_string(), fields),
+        None => format!("CREATE works CONTENT {{ {} }}", fields),
+    };
     let data: BTreeMap<String, Value> = BTreeMap::from([
-        ("name".into(), work.name.into()),
+        (
+            "name".into(),
+            if metadata.title.is_empty() {
+                work.name
+            } else {
+                metadata.title.clone()
+            }
+            .into(),
+        ),
         (
             "series".into(),
-            match work.series {
+            match metadata.series.clone() {
                 Some(v) => v,
                 None => "".to_owned(),
             }
             .into(),
         ),
+        ("series_index".into(), optional_value(metadata.series_index)),
         ("path".into(), work.path.into()),
+        ("root".into(), work.root.into()),
         (
             "files".into(),
             work.files
@@ -111,26 +495,286 @@ pub async fn create_work(work: Work) -> Result<(), String> {
                 .collect::<Vec<&str>>()
                 .into(),
         ),
+        (
+            "audio_files".into(),
+            audio_files
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<&str>>()
+                .into(),
+        ),
+        ("fingerprints".into(), to_value(&work.fingerprints)?),
+        ("cover".into(), to_value(&cover)?),
+        ("audio".into(), to_value(&work.audio)?),
+        ("duration".into(), work.duration.as_secs_f64().into()),
+        ("metadata".into(), to_value(&metadata)?),
+        ("review".into(), to_value(&review)?),
     ]);
-    match DB
+    let result = DB
         .get()
         .expect("DB does not exist")
         .execute(ass.as_str(), &SES, Some(data), false)
         .await
+        .map_err(|err| format!("failed to create work: {}", err))?;
+
+    let Some(Ok(created)) = into_iter_objects(result)?.next() else {
+        return Err("failed to create work: no record returned".into());
+    };
+    let work_id = created.get("id").map(|x| x.clone().as_string()).unwrap();
+
+    relate_people(&work_id, &metadata.authors, &metadata.narrators).await?;
+    Ok(existing.is_none())
+}
+
+/// Replaces the resume position of a work, leaving it a single `times`
+/// record.
+pub async fn save_position(work_id: &str, position: f64) -> Result<(), String> {
+    let work = thing(work_id).map_err(|_| "invalid work id".to_owned())?;
+    let data: BTreeMap<String, Value> = BTreeMap::from([
+        ("work".into(), Value::Thing(work)),
+        ("position".into(), position.into()),
+    ]);
+    DB.get()
+        .expect("DB does not exist")
+        .execute(
+            "DELETE times WHERE work = $work; CREATE times SET work = $work, position = $position",
+            &SES,
+            Some(data),
+            false,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("failed to save time: {}", err))
+}
+
+/// Deletes a work together with its listening time and author links. The
+/// folders merged into it are no longer skipped by the scanners.
+pub async fn delete_work(work_id: &str) -> Result<(), String> {
+    let ass = format!(
+        "DELETE times WHERE work = {id}; DELETE wrote WHERE out = {id}; \
+        DELETE narrated WHERE out = {id}; DELETE merged_paths WHERE work = {id}; \
+        DELETE {id}",
+        id = work_id
+    );
+    DB.get()
+        .expect("DB does not exist")
+        .execute(ass.as_str(), &SES, None, false)
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("failed to delete work: {}", err))
+}
+
+/// Flags the works of a mounted library root whose folder or audio files
+/// vanished as missing, and clears the flag once they are back.
+pub async fn mark_missing_works(root: &str) -> Result<(), String> {
+    let data: BTreeMap<String, Value> = BTreeMap::from([("root".into(), root.into())]);
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute(
+            "SELECT * FROM works WHERE root = $root",
+            &SES,
+            Some(data),
+            false,
+        )
+        .await
+        .map_err(|err| format!("failed to load works: {}", err))?;
+
+    for object in into_iter_objects(result)?.filter_map(Result::ok) {
+        let work = object_into_work(object);
+        let missing = !stored_path(&work.path).is_dir()
+            || work.audio_files.iter().any(|x| !stored_path(x).exists());
+        if missing == work.missing {
+            continue;
+        }
+        DB.get()
+            .expect("DB does not exist")
+            .execute(
+                format!("UPDATE {} SET missing = {}", work.id, missing).as_str(),
+                &SES,
+                None,
+                false,
+            )
+            .await
+            .map_err(|err| format!("failed to update work: {}", err))?;
+    }
+    Ok(())
+}
+
+/// Sets the root of works saved before library roots were tracked, so they
+/// are checked by `mark_missing_works` and `set_root_offline` too.
+pub async fn backfill_work_roots(root: &str) -> Result<(), String> {
+    let result = DB
+        .get()
+        .expect("DB does not exist")
+        .execute(
+            "SELECT id, path FROM works WHERE root = NONE OR root = ''",
+            &SES,
+            None,
+            false,
+        )
+        .await
+        .map_err(|err| format!("failed to load works: {}", err))?;
+
+    for object in into_iter_objects(result)?.filter_map(Result::ok) {
+        let (Some(id), Some(path)) = (object.get("id"), object.get("path")) else {
+            continue;
+        };
+        if !stored_path(&path.clone().as_string()).starts_with(root) {
+            continue;
+        }
+        let data: BTreeMap<String, Value> = BTreeMap::from([("root".into(), root.into())]);
+        DB.get()
+            .expect("DB does not exist")
+            .execute(
+                format!("UPDATE {} SET root = $root", id.clone().as_string()).as_str(),
+                &SES,
+                Some(data),
+                false,
+            )
+            .await
+            .map_err(|err| format!("failed to update work: {}", err))?;
+    }
+    Ok(())
+}
+
+/// Marks the works of a library root offline while it is not mounted, and
+/// back online once it is.
+pub async fn set_root_offline(root: &str, offline: bool) -> Result<(), String> {
+    let data: BTreeMap<String, Value> = BTreeMap::from([
+        ("root".into(), root.into()),
+        ("offline".into(), offline.into()),
+    ]);
+    DB.get()
+        .expect("DB does not exist")
+        .execute(
+            "UPDATE works SET offline = $offline WHERE root = $root",
+            &SES,
+            Some(data),
+            false,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("failed to update library root: {}", err))
+}
+
+/// Orders audio files by disc and track number when every file carries a
+/// distinct one in its tags, otherwise by natural file name order.
+pub fn order_audio_files(files: Vec<String>) -> Vec<String> {
+    let mut entries = files
+        .into_iter()
+        .map(|path| {
+            let position = read_track_position(&path);
+            (path, position)
+        })
+        .collect::<Vec<_>>();
+
+    let mut seen = HashSet::new();
+    let tagged = entries
+        .iter()
+        .all(|(_, position)| matches!(position, Some(x) if seen.insert(*x)));
+
+    entries.sort_by(|(a_path, a_position), (b_path, b_position)| {
+        if tagged {
+            a_position
+                .cmp(b_position)
+                .then_with(|| natural_cmp(a_path, b_path))
+        } else {
+            natural_cmp(a_path, b_path)
+        }
+    });
+
+    entries.into_iter().map(|(path, _)| path).collect()
+}
+
+fn read_track_position(path: &str) -> Option<(u32, u32)> {
+    let file = lofty::Probe::open(stored_path(path))
+        .ok()?
+        .options(lofty::ParseOptions::new().read_properties(false))
+        .read()
+        .ok()?;
+    let tag = file.primary_tag().or_else(|| file.first_tag())?;
+    Some((tag.disk().unwrap_or(1), tag.track()?))
+}
+
+/// Keeps a manually chosen order for the files still present, appending any
+/// newly found files in their scanned order.
+pub fn keep_custom_order(custom: Vec<String>, scanned: Vec<String>) -> Vec<String> {
+    let mut ordered: Vec<String> = custom.into_iter().filter(|x| scanned.contains(x)).collect();
+    for path in scanned {
+        if !ordered.contains(&path) {
+            ordered.push(path);
+        }
+    }
+    ordered
+}
+
+/// Replaces the `wrote` and `narrated` relations of a work, creating any
+/// author records that do not exist yet.
+pub async fn relate_people(
+    work_id: &str,
+    authors: &[String],
+    narrators: &[String],
+) -> Result<(), String> {
+    let mut ass =
+        format!("DELETE wrote WHERE out = {work_id}; DELETE narrated WHERE out = {work_id};");
+    for author in authors {
+        let author_id = create_author(author.clone()).await?;
+        ass.push_str(&format!(" RELATE authors:{author_id}->wrote->{work_id};"));
+    }
+    for narrator in narrators {
+        let author_id = create_author(narrator.clone()).await?;
+        ass.push_str(&format!(
+            " RELATE authors:{author_id}->narrated->{work_id};"
+        ));
+    }
+    match DB
+        .get()
+        .expect("DB does not exist")
+        .execute(ass.as_str(), &SES, None, false)
+        .await
     {
         Ok(_) => Ok(()),
-        Err(err) => Err(format!("failed to create work: {}", err)),
+        Err(err) => Err(format!("failed to relate people: {}", err)),
     }
 }
 
-pub async fn create_author(author_name: String) -> Result<String, String> {
-    let author_id = string_to_id(author_name.clone());
+/// Stores edited metadata on a work and mirrors it into the fields the
+/// library views read.
+pub async fn save_work_metadata(work_id: &str, metadata: &WorkMetadata) -> Result<(), String> {
+    if metadata.title.trim().is_empty() {
+        return Err("title cannot be empty".into());
+    }
+
     let ass = format!(
-        "CREATE authors:{} CONTENT {{ name: $name }}",
-        author_id.clone()
+        "UPDATE {} MERGE {{ name: $name, series: $series, series_index: $series_index, metadata: $metadata }}",
+        work_id
     );
-    let data: BTreeMap<String, Value> =
-        BTreeMap::from([("name".into(), author_name.replace('\'', r"\'").into())]);
+    let data: BTreeMap<String, Value> = BTreeMap::from([
+        ("name".into(), metadata.title.clone().into()),
+        (
+            "series".into(),
+            metadata.series.clone().unwrap_or_default().into(),
+        ),
+        ("series_index".into(), optional_value(metadata.series_index)),
+        ("metadata".into(), to_value(metadata)?),
+    ]);
+    if let Err(err) = DB
+        .get()
+        .expect("DB does not exist")
+        .execute(ass.as_str(), &SES, Some(data), false)
+        .await
+    {
+        return Err(format!("failed to save work metadata: {}", err));
+    }
+
+    relate_people(work_id, &metadata.authors, &metadata.narrators).await
+}
+
+pub async fn create_author(author_name: String) -> Result<String, String> {
+    let author_id = person_id(&author_name);
+    let ass = format!("UPDATE authors:{} SET name = $name", author_id.clone());
+    let data: BTreeMap<String, Value> = BTreeMap::from([("name".into(), author_name.into())]);
     match DB
         .get()
         .expect("DB does not exist")
@@ -142,79 +786,195 @@ pub async fn create_author(author_name: String) -> Result<String, String> {
     }
 }
 
-pub fn read_file_metadata(path: String) -> Result<TrackMetadata, ReadFileMetadataError> {
-    let Ok(file) = lofty::read_from_path(path.clone()) else {
-        return Err(ReadFileMetadataError {});
-    };
+/// Reads the duration, titles and chapters of an audio file. Files with
+/// chapter support but no chapter track are read as a single chapter.
+pub fn read_file_metadata(path: String) -> Result<TrackMetadata, String> {
+    let file = lofty::read_from_path(stored_path(&path))
+        .map_err(|err| format!("failed to read audio file: {}", err))?;
 
-    let Some(tag) = file.primary_tag() else {
-        return Err(ReadFileMetadataError {});
+    // untagged files still have a duration and audio properties
+    let tag = file.primary_tag().or_else(|| file.first_tag());
+    let tag_string = |key: lofty::ItemKey| {
+        tag.and_then(|x| x.get_string(&key))
+            .unwrap_or_default()
+            .to_owned()
     };
+    let track_title = tag_string(lofty::ItemKey::TrackTitle);
+    let duration = file.properties().duration();
 
     let mut chapters = vec![];
-
-    let c_path = PathBuf::from(path.clone());
-    if c_path.extension().is_some()
-        && AUDIO_FILE_WITH_CHAPTERS_EXTENSIONS
-            .contains(&c_path.extension().unwrap().to_str().unwrap())
-    {
-        let Ok(f) = File::open(path.clone())  else {
-            return Err(ReadFileMetadataError {});
-        };
-        let size = f.metadata().unwrap().len();
-        let reader = BufReader::new(f);
-        let Ok(mut mp4) = mp4::Mp4Reader::read_header(reader, size)  else {
-            return Err(ReadFileMetadataError {});
-        };
-
-        let Some(track) = mp4
-            .tracks()
-            .values()
-            .find(|x| x.media_type().is_err()) else {
-                return Err(ReadFileMetadataError {});
-            };
-        let track_id = track.track_id();
-        for i in 0..track.sample_count() {
-            let Ok(Some(sample)) = mp4.read_sample(track_id, i + 1) else {
-                return Err(ReadFileMetadataError {});
-            };
-
-            chapters.push(Chapter {
-                title: format!("Chapter {}", i),
-                length: if sample.duration == 0 {
-                    file.properties().duration() - Duration::from_millis(sample.start_time)
-                } else {
-                    Duration::from_millis(sample.duration.into())
-                },
-            });
-        }
-    } else {
+    if has_extension(&stored_path(&path), &AUDIO_FILE_WITH_CHAPTERS_EXTENSIONS) {
+        chapters = read_mp4_chapters(&path, duration)?;
+    }
+    if chapters.is_empty() {
         chapters.push(Chapter {
-            title: tag
-                .get_string(&lofty::ItemKey::TrackTitle)
-                .unwrap_or_default()
-                .to_owned(),
-            length: file.properties().duration(),
+            title: track_title.clone(),
+            length: duration,
         });
     }
 
+    let properties = read_audio_properties(&path, &file);
     let metadata = TrackMetadata {
         path,
-        duration: file.properties().duration(),
-        track_title: tag
-            .get_string(&lofty::ItemKey::TrackTitle)
-            .unwrap_or_default()
-            .to_owned(),
-        track_author: tag
-            .get_string(&lofty::ItemKey::TrackArtist)
-            .unwrap_or_default()
-            .to_owned(),
-        album_title: tag
-            .get_string(&lofty::ItemKey::AlbumTitle)
-            .unwrap_or_default()
-            .to_owned(),
+        duration,
+        track_title,
+        track_author: tag_string(lofty::ItemKey::TrackArtist),
+        album_title: tag_string(lofty::ItemKey::AlbumTitle),
         chapters,
+        properties,
     };
 
     Ok(metadata)
 }
+
+fn read_audio_properties(path: &str, file: &TaggedFile) -> AudioProperties {
+    let properties = file.properties();
+    let codec = match file.file_type() {
+        FileType::MPEG => "MP3".to_owned(),
+        FileType::WAV => "PCM".to_owned(),
+        FileType::MP4 => read_mp4_codec(path).unwrap_or_default(),
+        other => format!("{:?}", other),
+    };
+
+    AudioProperties {
+        container: format!("{:?}", file.file_type()),
+        bitrate: properties
+            .audio_bitrate()
+            .or_else(|| properties.overall_bitrate()),
+        sample_rate: properties.sample_rate(),
+        channels: properties.channels(),
+        bit_depth: properties.bit_depth(),
+        file_size: fs::metadata(stored_path(path))
+            .map(|x| x.len())
+            .unwrap_or_default(),
+        drm: has_extension(&stored_path(path), &DRM_FILE_EXTENSIONS)
+            || DRM_CODECS.contains(&codec.as_str()),
+        codec,
+    }
+}
+
+/// Reads the codec from the sample entry of the audio track, unknown entries
+/// keep their four character code.
+fn read_mp4_codec(path: &str) -> Option<String> {
+    let mut reader = BufReader::new(File::open(stored_path(path)).ok()?);
+    let file = Mp4File::read_from(&mut reader, ParseOptions::new().read_properties(true)).ok()?;
+
+    Some(match file.properties().codec() {
+        Mp4Codec::Unknown(name) => name.clone(),
+        codec => format!("{:?}", codec),
+    })
+}
+
+fn read_mp4_chapters(path: &str, duration: Duration) -> Result<Vec<Chapter>, String> {
+    let f = File::open(stored_path(path))
+        .map_err(|err| format!("failed to open audio file: {}", err))?;
+    let size = f
+        .metadata()
+        .map_err(|err| format!("failed to read file size: {}", err))?
+        .len();
+    let reader = BufReader::new(f);
+    let mut mp4 = mp4::Mp4Reader::read_header(reader, size)
+        .map_err(|err| format!("failed to read mp4 header: {}", err))?;
+
+    let Some(track) = mp4.tracks().values().find(|x| x.media_type().is_err()) else {
+        return Ok(vec![]);
+    };
+    let track_id = track.track_id();
+
+    let mut chapters = vec![];
+    for i in 0..track.sample_count() {
+        let Ok(Some(sample)) = mp4.read_sample(track_id, i + 1) else {
+            return Err(format!("failed to read chapter {}", i));
+        };
+
+        chapters.push(Chapter {
+            title: format!("Chapter {}", i),
+            length: if sample.duration == 0 {
+                duration.saturating_sub(Duration::from_millis(sample.start_time))
+            } else {
+                Duration::from_millis(sample.duration.into())
+            },
+        });
+    }
+
+    Ok(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "audiobookplayer-utils-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn work_object(path: &Path, root: &Path, offline: bool) -> Object {
+        Object::from(BTreeMap::from([
+            ("path".to_owned(), Value::from(path_to_stored(path))),
+            ("root".to_owned(), Value::from(path_to_stored(root))),
+            ("offline".to_owned(), Value::from(offline)),
+        ]))
+    }
+
+    #[test]
+    fn person_ids_tell_names_apart() {
+        assert_ne!(
+            person_id("Łukasz Orbitowski"),
+            person_id("Ślukasz Orbitowski")
+        );
+        assert_ne!(person_id("村上春樹"), person_id("東野圭吾"));
+        assert_eq!(person_id("J.R.R. Tolkien"), person_id("Tolkien, J.R.R."));
+    }
+
+    #[test]
+    fn moved_work_is_gone_from_a_mounted_root() {
+        let root = test_root("moved");
+        fs::create_dir_all(root.join("Present")).unwrap();
+        let gone = work_object(&root.join("Gone"), &root, false);
+
+        let found = moved_candidate(vec![
+            work_object(&root.join("Present"), &root, false),
+            gone.clone(),
+        ]);
+
+        assert_eq!(found, Some(gone));
+    }
+
+    #[test]
+    fn works_of_an_offline_root_are_not_moved() {
+        let root = test_root("offline");
+        let unmounted = root.join("unmounted");
+
+        assert_eq!(
+            moved_candidate(vec![work_object(
+                &unmounted.join("Book"),
+                &unmounted,
+                false
+            )]),
+            None
+        );
+        assert_eq!(
+            moved_candidate(vec![work_object(&root.join("Book"), &root, true)]),
+            None
+        );
+    }
+
+    #[test]
+    fn several_gone_works_are_not_moved() {
+        let root = test_root("ambiguous");
+
+        let found = moved_candidate(vec![
+            work_object(&root.join("First"), &root, false),
+            work_object(&root.join("Second"), &root, false),
+        ]);
+
+        assert_eq!(found, None);
+    }
+}
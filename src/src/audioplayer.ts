@@ -196,6 +196,18 @@ export interface TrackMetadata {
     albumTitle: string;
     duration: Duration;
     chapters: Chapter[];
+    properties: AudioProperties;
+}
+
+export interface AudioProperties {
+    codec: string;
+    container: string;
+    bitrate?: number;
+    sample_rate?: number;
+    channels?: number;
+    bit_depth?: number;
+    file_size: number;
+    drm: boolean;
 }
 
 export interface Duration {
@@ -1,15 +1,104 @@
+import type { TrackMetadata } from "./audioplayer";
+
 export interface Book {
     id: string,
     name: string,
     author: string,
+    authors: Author[],
+    narrators: Author[],
     series: string,
+    series_index?: number,
 
     path: string,
+    display_path: string,
+    root: string,
+    offline: boolean,
+    missing: boolean,
     files: string[],
     audio_files: string[],
     image_files: string[],
+    duration: { secs: number, nanos: number },
+    cover?: CoverArt,
+    audio: WorkAudio,
+
+    metadata: WorkMetadata,
+    review: ReviewFlag[],
+}
+
+export interface WorkAudio {
+    codecs: string[],
+    containers: string[],
+    min_bitrate?: number,
+    max_bitrate?: number,
+    sample_rate?: number,
+    channels?: number,
+    bit_depth?: number,
+    size: number,
+    drm: boolean,
+}
+
+export interface LibraryFilter {
+    codec?: string,
+    container?: string,
+    min_bitrate?: number,
+    max_bitrate?: number,
+    channels?: number,
+    drm?: boolean,
+    genre?: string,
+    min_year?: number,
+    max_year?: number,
+}
+
+export interface TrackReadError {
+    path: string,
+    message: string,
+}
+
+export interface WorkTracks {
+    tracks: TrackMetadata[],
+    errors: TrackReadError[],
+}
+
+export interface CoverArt {
+    hash: string,
+    path: string,
+    small?: string,
+    medium?: string,
+}
+
+export interface ReviewFlag {
+    field: MetadataField,
+    folder: string,
+    tags: string,
 }
 
+export interface Author {
+    id: string,
+    name: string,
+}
+
+export interface WorkMetadata {
+    title: string,
+    subtitle?: string,
+    authors: string[],
+    narrators: string[],
+    series?: string,
+    series_index?: number,
+    description?: string,
+    publisher?: string,
+    published_year?: number,
+    language?: string,
+    copyright?: string,
+    isbn?: string,
+    asin?: string,
+    genres: string[],
+
+    locked: MetadataField[],
+}
+
+export type MetadataField = "Title" | "Subtitle" | "Authors" | "Narrators" | "Series" | "SeriesIndex"
+    | "Description" | "Publisher" | "PublishedYear" | "Language" | "Copyright" | "Isbn" | "Asin" | "Genres";
+
 interface SubWork {
 }
 
@@ -17,12 +106,20 @@ interface Work {
     subworks: SubWork[];
 }
 
-interface Author {
+interface LibraryAuthor {
     works: Work[];
 }
 
+export type DuplicateReason = "Title" | "Author" | "Duration" | "Fingerprint";
+
+export interface DuplicateGroup {
+    works: Book[],
+    confidence: number,
+    reasons: DuplicateReason[],
+}
+
 export interface Library {
-    authors: Author[];
+    authors: LibraryAuthor[];
 }
 
 export interface Stats {
@@ -40,11 +137,132 @@ export class Segment {
 export class Settings {
     library_location: string = "";
     library_style: LibraryStyle = LibraryStyle.Folder;
+    library_roots: LibraryRoot[] = [];
+    folder_templates: string[] = [];
+    metadata_template: MetadataTemplate;
+    metadata_providers: MetadataProviderSettings;
+}
+
+export interface MetadataProviderSettings {
+    open_library_url: string;
+    open_library_covers_url: string;
+    google_books_url: string;
+}
+
+export interface MetadataQuery {
+    title?: string;
+    author?: string;
+    isbn?: string;
+}
+
+export interface MetadataCandidate {
+    provider: string;
+    id: string;
+    metadata: WorkMetadata;
+    cover_url?: string;
+}
+
+export interface LibraryRoot {
+    path: string;
+    style: LibraryStyle;
+    folder_templates: string[];
+    enabled: boolean;
+}
+
+export type TagSource = { Key: string } | { Custom: string };
+
+export interface MetadataTemplate {
+    author: TagSource[];
+    title: TagSource[];
+    narrator: TagSource[];
+    series: TagSource[];
+    series_index: TagSource[];
+    year: TagSource[];
+    description: TagSource[];
+    genre: TagSource[];
+    publisher: TagSource[];
+    copyright: TagSource[];
+    language: TagSource[];
+}
+
+export type ScanPhase = "FindingFiles" | "ReadingFiles" | "Saving" | "Complete" | "Cancelled";
+
+export type ScanFailureKind = "FileRead" | "TagRead" | "AuthorRead" | "AlbumRead" | "Save"
+    | "DirectoryRead" | "InvalidPath";
+
+export type ScanError = "AlreadyRunning" | "NoLibraryRoots" | { Settings: string };
+
+export interface ProgressEntry {
+    relative_path: string,
+    title: string,
+    author: string,
+    fingerprints: string[],
+    position: number,
+}
+
+export interface ProgressImportReport {
+    matched: number,
+    unmatched: ProgressEntry[],
+}
+
+export type RestoreMode = "Replace" | "Merge";
+
+export type ExportFormat = "Audiobookshelf" | "Opf";
+
+export interface ExportFailure {
+    path: string,
+    message: string,
+}
+
+export interface ExportReport {
+    written: string[],
+    skipped: string[],
+    failures: ExportFailure[],
+}
+
+export interface ScanFailure {
+    path: string,
+    kind: ScanFailureKind,
+    message: string,
+}
+
+export const ScanFailureLegends: [ScanFailureKind, string][] = [
+    ["FileRead", "Failed to read file"],
+    ["TagRead", "Failed to read tags"],
+    ["AuthorRead", "Failed to read author tag"],
+    ["AlbumRead", "Failed to read album tag"],
+    ["Save", "Failed to save work"],
+    ["DirectoryRead", "Failed to read folder"],
+    ["InvalidPath", "Path is not valid UTF-8"],
+];
+
+export interface ScanReportSummary {
+    id: string,
+    started_at: number,
+    duration: { secs: number, nanos: number },
+    style: LibraryStyle | null,
+    files_seen: number,
+    works_added: number,
+    works_updated: number,
+    cancelled: boolean,
+    failed: number,
+}
+
+export interface ScanProgress {
+    phase: ScanPhase,
+    root?: string,
+    total: number,
+    completed: number,
+    failed: number,
+    current_path?: string,
+    eta?: { secs: number, nanos: number },
+    failure?: ScanFailure,
 }
 
 export enum LibraryStyle {
     Folder = "Folder",
     Metadata = "Metadata",
+    Hybrid = "Hybrid",
 }
 
 export const PossibleTags = [
@@ -1,3 +1,5 @@
+import type { Book } from "./types";
+
 export function secondsToFormatted(value: number) {
     let string = "";
     let s = Math.floor(value % 3600 % 60);
@@ -22,4 +24,8 @@ export const groupBy = <K, T>(list: T[], selector: (x: T) => K) => {
 
         return a;
     }, new Map<K, T[]>());
-}
\ No newline at end of file
+}
+/** Cached cover of a book at the given size, falling back to its folder images. */
+export function coverPath(book: Book, size: "small" | "medium"): string | undefined {
+    return book.cover?.[size] ?? book.cover?.path ?? book.image_files[0];
+}